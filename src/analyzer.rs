@@ -0,0 +1,229 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    export,
+    probe::{self, Probe},
+    pumpkin_plot::PumpkinWorld,
+};
+
+/// The longest window `/rp record` records, as every tick of it is kept in memory.
+pub const MAX_TICKS: i32 = 100_000;
+
+/// Signals in a VCD file are identified by printable ASCII characters from `!` to `~`.
+const FIRST_IDENTIFIER_CHAR: u8 = b'!';
+const IDENTIFIER_CHARS: usize = 94;
+
+struct Signal {
+    name: String,
+    pos: mchprs_blocks::BlockPos,
+    value: u8,
+}
+
+/// The power level of the probes of a circuit after every tick, for a fixed number of ticks.
+pub struct Recording {
+    /// The player that started the recording
+    pub owner: Uuid,
+    /// Number of ticks to record
+    pub ticks: u64,
+    /// Number of ticks recorded so far
+    pub recorded: u64,
+    signals: Vec<Signal>,
+    initial: Vec<u8>,
+    /// Tick, signal index and new power level of every change
+    changes: Vec<(u64, usize, u8)>,
+}
+
+impl Recording {
+    /// Starts recording `probes`, with `world` holding the changes not yet seen by the probes.
+    pub fn new(owner: Uuid, probes: &[Probe], world: &PumpkinWorld, ticks: u64) -> Self {
+        let signals: Vec<Signal> = probes
+            .iter()
+            .map(|probe| Signal {
+                name: probe.name.clone(),
+                pos: probe.pos,
+                value: probe::flushed_power(world, probe.pos).unwrap_or(probe.power),
+            })
+            .collect();
+
+        Recording {
+            owner,
+            ticks,
+            recorded: 0,
+            initial: signals.iter().map(|signal| signal.value).collect(),
+            signals,
+            changes: Vec::new(),
+        }
+    }
+
+    /// Records the state after one more tick, from the changes flushed into `world` so far.
+    pub fn sample(&mut self, world: &PumpkinWorld) {
+        self.recorded += 1;
+        for (index, signal) in self.signals.iter_mut().enumerate() {
+            let Some(value) = probe::flushed_power(world, signal.pos) else {
+                continue;
+            };
+            if value != signal.value {
+                signal.value = value;
+                self.changes.push((self.recorded, index, value));
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.recorded >= self.ticks
+    }
+
+    /// Writes the recording as a Value Change Dump with one 4 bit signal per probe.
+    ///
+    /// One time unit is one redstone tick, shown as the 100 ms it takes in vanilla.
+    pub fn to_vcd(&self, circuit: &str) -> String {
+        let mut vcd = String::new();
+        // Writing to a string never fails
+        let _ = writeln!(vcd, "$version pumpkin redpiler $end");
+        let _ = writeln!(vcd, "$timescale 100 ms $end");
+        let _ = writeln!(vcd, "$scope module {} $end", export::file_name(circuit));
+        for (index, signal) in self.signals.iter().enumerate() {
+            let _ = writeln!(
+                vcd,
+                "$var wire 4 {} {} $end",
+                identifier(index),
+                export::file_name(&signal.name)
+            );
+        }
+        let _ = writeln!(vcd, "$upscope $end");
+        let _ = writeln!(vcd, "$enddefinitions $end");
+
+        let _ = writeln!(vcd, "#0");
+        let _ = writeln!(vcd, "$dumpvars");
+        for (index, value) in self.initial.iter().enumerate() {
+            let _ = writeln!(vcd, "b{value:04b} {}", identifier(index));
+        }
+        let _ = writeln!(vcd, "$end");
+
+        let mut time = 0;
+        for &(tick, index, value) in &self.changes {
+            if tick != time {
+                time = tick;
+                let _ = writeln!(vcd, "#{time}");
+            }
+            let _ = writeln!(vcd, "b{value:04b} {}", identifier(index));
+        }
+        // Mark the end of the window, so signals that stopped changing are shown until the end
+        if self.recorded != time {
+            let _ = writeln!(vcd, "#{}", self.recorded);
+        }
+        vcd
+    }
+}
+
+/// Writes the recording into `data_folder`, named after the circuit and the current time.
+pub fn write_vcd(
+    data_folder: &Path,
+    circuit: &str,
+    recording: &Recording,
+) -> Result<PathBuf, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = data_folder.join(format!("{}-{timestamp}.vcd", export::file_name(circuit)));
+
+    std::fs::write(&path, recording.to_vcd(circuit))
+        .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+    Ok(path)
+}
+
+/// The identifier of the signal at `index`, one or more printable characters.
+fn identifier(mut index: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push(char::from(
+            FIRST_IDENTIFIER_CHAR + (index % IDENTIFIER_CHARS) as u8,
+        ));
+        index /= IDENTIFIER_CHARS;
+        if index == 0 {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mchprs_blocks::{BlockPos, blocks::Block};
+
+    use super::*;
+    use crate::probe::tests::probe;
+
+    fn set_lamp(world: &mut PumpkinWorld, x: i32, lit: bool) {
+        world
+            .set_events
+            .insert(BlockPos::new(x, 0, 0), Block::RedstoneLamp { lit }.get_id());
+    }
+
+    #[test]
+    fn writes_changes_per_tick() {
+        let probes = [
+            probe("clk", BlockPos::new(0, 0, 0), 0),
+            probe("out", BlockPos::new(1, 0, 0), 15),
+        ];
+        let mut world = PumpkinWorld::new(BlockPos::new(0, 0, 0));
+        let mut recording = Recording::new(Uuid::nil(), &probes, &world, 4);
+
+        set_lamp(&mut world, 0, true);
+        recording.sample(&world);
+        recording.sample(&world);
+        set_lamp(&mut world, 0, false);
+        set_lamp(&mut world, 1, false);
+        recording.sample(&world);
+        recording.sample(&world);
+        assert!(recording.is_done());
+
+        let vcd = recording.to_vcd("my cpu");
+        let expected = "\
+$version pumpkin redpiler $end
+$timescale 100 ms $end
+$scope module my_cpu $end
+$var wire 4 ! clk $end
+$var wire 4 \" out $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+b0000 !
+b1111 \"
+$end
+#1
+b1111 !
+#3
+b0000 !
+b0000 \"
+#4
+";
+        assert_eq!(vcd, expected);
+    }
+
+    #[test]
+    fn starts_from_unseen_changes() {
+        let mut world = PumpkinWorld::new(BlockPos::new(0, 0, 0));
+        set_lamp(&mut world, 0, true);
+
+        let probes = [probe("lamp", BlockPos::new(0, 0, 0), 0)];
+        let recording = Recording::new(Uuid::nil(), &probes, &world, 1);
+
+        assert_eq!(recording.initial, [15]);
+    }
+
+    #[test]
+    fn creates_unique_identifiers() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+
+        let ids: std::collections::HashSet<_> = (0..10_000).map(identifier).collect();
+        assert_eq!(ids.len(), 10_000);
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::{
+    probe::{self, Probe},
+    pumpkin_plot::PumpkinWorld,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        // Two character operators first, so `<=` is not read as `<`
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    fn operator(self) -> &'static str {
+        Self::OPERATORS
+            .iter()
+            .find(|(_, comparison)| *comparison == self)
+            .map_or("", |(operator, _)| *operator)
+    }
+
+    fn holds(self, power: u8, value: u8) -> bool {
+        match self {
+            Comparison::Equal => power == value,
+            Comparison::NotEqual => power != value,
+            Comparison::Less => power < value,
+            Comparison::LessOrEqual => power <= value,
+            Comparison::Greater => power > value,
+            Comparison::GreaterOrEqual => power >= value,
+        }
+    }
+}
+
+/// When a breakpoint pauses its circuit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    /// The power level starts to compare to a value like this
+    Compare(Comparison, u8),
+    /// The power level goes from 0 to anything higher
+    Rising,
+    /// The power level goes back to 0
+    Falling,
+}
+
+impl Condition {
+    /// Parses `rising`, `falling` or a comparison like `== 15` or `>=8`.
+    pub fn parse(condition: &str) -> Result<Self, String> {
+        let condition = condition.trim();
+        match condition {
+            "rising" => return Ok(Condition::Rising),
+            "falling" => return Ok(Condition::Falling),
+            _ => {}
+        }
+
+        let invalid = || {
+            format!(
+                "Invalid condition {condition}, expected rising, falling or a comparison like == 15"
+            )
+        };
+        let (comparison, value) = Comparison::OPERATORS
+            .iter()
+            .find_map(|(operator, comparison)| {
+                let value = condition.strip_prefix(*operator)?;
+                Some((*comparison, value))
+            })
+            .ok_or_else(invalid)?;
+        let value = value.trim().parse().map_err(|_| invalid())?;
+        if value > 15 {
+            return Err(format!("Power levels go up to 15, not {value}"));
+        }
+
+        Ok(Condition::Compare(comparison, value))
+    }
+
+    /// Whether the condition became true going from power level `previous` to `power`.
+    pub fn triggers(self, previous: u8, power: u8) -> bool {
+        match self {
+            Condition::Compare(comparison, value) => {
+                !comparison.holds(previous, value) && comparison.holds(power, value)
+            }
+            Condition::Rising => previous == 0 && power > 0,
+            Condition::Falling => previous > 0 && power == 0,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Compare(comparison, value) => write!(f, "{} {value}", comparison.operator()),
+            Condition::Rising => write!(f, "rising"),
+            Condition::Falling => write!(f, "falling"),
+        }
+    }
+}
+
+/// Pauses a circuit on the tick the power level of a probe meets a condition.
+#[derive(Clone)]
+pub struct Breakpoint {
+    /// Name of the probe
+    pub probe: String,
+    /// The player that set the breakpoint
+    pub owner: Uuid,
+    pub condition: Condition,
+    pos: mchprs_blocks::BlockPos,
+    power: u8,
+}
+
+impl Breakpoint {
+    /// Breaks on `probe`, with `world` holding the changes not yet seen by the probe.
+    pub fn new(probe: &Probe, owner: Uuid, condition: Condition, world: &PumpkinWorld) -> Self {
+        Breakpoint {
+            probe: probe.name.clone(),
+            owner,
+            condition,
+            pos: probe.pos,
+            power: probe::flushed_power(world, probe.pos).unwrap_or(probe.power),
+        }
+    }
+
+    /// Checks the condition after a tick, from the changes flushed into `world` so far. Returns
+    /// the power level when the condition became true.
+    pub fn check(&mut self, world: &PumpkinWorld) -> Option<u8> {
+        let power = probe::flushed_power(world, self.pos).unwrap_or(self.power);
+        let previous = std::mem::replace(&mut self.power, power);
+        self.condition.triggers(previous, power).then_some(power)
+    }
+}
+
+/// A breakpoint that paused its circuit.
+pub struct BreakpointHit {
+    pub probe: String,
+    pub owner: Uuid,
+    pub condition: Condition,
+    pub power: u8,
+    /// The tick the circuit stopped on, counted from when it was compiled
+    pub tick: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use mchprs_blocks::{BlockPos, blocks::Block};
+
+    use super::*;
+    use crate::probe::tests::probe;
+
+    #[test]
+    fn parses_conditions() {
+        let conditions = [
+            ("rising", Condition::Rising),
+            (" falling ", Condition::Falling),
+            ("== 15", Condition::Compare(Comparison::Equal, 15)),
+            ("<=3", Condition::Compare(Comparison::LessOrEqual, 3)),
+            ("< 3", Condition::Compare(Comparison::Less, 3)),
+            ("!= 0", Condition::Compare(Comparison::NotEqual, 0)),
+        ];
+        for (text, condition) in conditions {
+            assert_eq!(Condition::parse(text), Ok(condition), "{text}");
+            assert_eq!(Condition::parse(&condition.to_string()), Ok(condition));
+        }
+
+        for text in ["", "up", "== ", "= 15", "== 16", "> -1"] {
+            assert!(Condition::parse(text).is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn triggers_when_the_condition_becomes_true() {
+        let full = Condition::Compare(Comparison::Equal, 15);
+        assert!(full.triggers(14, 15));
+        assert!(!full.triggers(15, 15));
+        assert!(!full.triggers(15, 14));
+
+        assert!(Condition::Rising.triggers(0, 1));
+        assert!(!Condition::Rising.triggers(1, 15));
+        assert!(Condition::Falling.triggers(15, 0));
+        assert!(!Condition::Falling.triggers(0, 0));
+    }
+
+    #[test]
+    fn checks_flushed_changes() {
+        let probe = probe("lamp", BlockPos::new(0, 0, 0), 0);
+        let mut world = PumpkinWorld::new(BlockPos::new(0, 0, 0));
+        let mut breakpoint = Breakpoint::new(&probe, Uuid::nil(), Condition::Rising, &world);
+
+        assert_eq!(breakpoint.check(&world), None);
+        world
+            .set_events
+            .insert(probe.pos, Block::RedstoneLamp { lit: true }.get_id());
+        assert_eq!(breakpoint.check(&world), Some(15));
+        assert_eq!(breakpoint.check(&world), None);
+    }
+}
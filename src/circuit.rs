@@ -0,0 +1,710 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, mpsc},
+};
+
+use mchprs_redpiler::Compiler;
+use mchprs_world::World;
+use pumpkin_util::math::position::BlockPos;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::{
+    analyzer::Recording,
+    breakpoint::{Breakpoint, BreakpointHit, Condition},
+    compile,
+    config::{self, Config},
+    export::{self, DotExport},
+    fixed_world::TestWorld,
+    inspect::{self, Graph},
+    persist::{CircuitInfo, array_to_pos},
+    probe::{self, Probe, ProbeChange},
+    pumpkin_plot::{self, BlockCache, PumpkinWorld},
+    scheduler::{
+        LagMonitor, LagPolicy, SYNC_POLL_INTERVAL, TICK_BATCH, TickClock, TickRate,
+        UNLIMITED_TIME_SLICE,
+    },
+};
+
+/// The most ticks `/rp step` runs at once.
+pub const MAX_STEP_TICKS: i32 = 100_000;
+
+/// Input from players, passed to the tick loop so event handlers never wait on a running circuit.
+pub enum Input {
+    /// A block was right clicked
+    Use(mchprs_blocks::BlockPos),
+}
+
+/// The part of a circuit that changes while it runs.
+///
+/// Ticks run in batches of [`TICK_BATCH`] and the lock is released in between, so commands wait
+/// for one batch at most. It is never held across an `.await`, world changes are applied after
+/// the lock is released.
+pub struct Simulation {
+    pub compiler: Compiler,
+    clock: TickClock,
+    lag: LagMonitor,
+    /// Whether the compiled circuit changed since the last flush
+    dirty: bool,
+    next_flush: Instant,
+    inputs: mpsc::Receiver<Input>,
+    /// Set when the circuit is reset or invalidated, after which the tick loop leaves it alone
+    stopped: bool,
+    /// A paused circuit only runs ticks through [`PlotData::step`]
+    paused: bool,
+    /// Probes are lost when the circuit is compiled again
+    probes: Vec<Probe>,
+    recording: Option<Recording>,
+    breakpoints: Vec<Breakpoint>,
+    /// Number of ticks run since the circuit was compiled
+    tick_count: u64,
+    /// Changes flushed from the compiler that are not applied to the world yet
+    pending: PumpkinWorld,
+    /// Changes left out by [`config::CompileFlags::render_io_only`], written by the next step or
+    /// reset so the world never keeps stale wiring
+    hidden: HashMap<mchprs_blocks::BlockPos, u32>,
+    events: Events,
+}
+
+impl Simulation {
+    /// Runs `ticks` ticks, one at a time while recording or with breakpoints. Returns the number
+    /// of ticks that ran, which is less when a breakpoint paused the circuit.
+    fn tick(&mut self, ticks: u64) -> u64 {
+        for ran in 0..ticks {
+            if self.recording.is_none() && self.breakpoints.is_empty() {
+                self.compiler.tickn(ticks - ran);
+                self.tick_count += ticks - ran;
+                return ticks;
+            }
+
+            self.compiler.tickn(1);
+            self.tick_count += 1;
+            // The state in between flushes is only visible by flushing after every tick
+            self.compiler.flush(&mut self.pending);
+
+            if let Some(recording) = &mut self.recording {
+                recording.sample(&self.pending);
+                if recording.is_done() {
+                    self.events.recordings.extend(self.recording.take());
+                }
+            }
+
+            let mut hit = false;
+            for breakpoint in &mut self.breakpoints {
+                let Some(power) = breakpoint.check(&self.pending) else {
+                    continue;
+                };
+                self.events.breakpoint_hits.push(BreakpointHit {
+                    probe: breakpoint.probe.clone(),
+                    owner: breakpoint.owner,
+                    condition: breakpoint.condition,
+                    power,
+                    tick: self.tick_count,
+                });
+                hit = true;
+            }
+            if hit {
+                self.paused = true;
+                return ran + 1;
+            }
+        }
+        ticks
+    }
+
+    /// Flushes the compiler and takes every change that is not applied yet.
+    fn flush(&mut self) -> PumpkinWorld {
+        self.compiler.flush(&mut self.pending);
+        let base = self.pending.base;
+        let world = std::mem::replace(&mut self.pending, PumpkinWorld::new(base));
+        // Probes see every change, even the ones that are not rendered
+        let changes = probe::observe(&mut self.probes, &world);
+        self.events.probe_changes.extend(changes);
+        world
+    }
+
+    fn handle_inputs(&mut self) {
+        while let Ok(input) = self.inputs.try_recv() {
+            match input {
+                Input::Use(pos) => self.compiler.on_use_block(pos),
+            }
+            self.dirty = true;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The tick rate achieved during the last second the circuit ran.
+    pub fn measured_rtps(&self) -> Option<f64> {
+        self.lag.measured_rtps
+    }
+}
+
+/// What happened to a circuit that its players should hear about.
+#[derive(Default)]
+pub struct Events {
+    pub probe_changes: Vec<ProbeChange>,
+    /// Recordings that reached the end of their window
+    pub recordings: Vec<Recording>,
+    pub breakpoint_hits: Vec<BreakpointHit>,
+}
+
+impl Events {
+    fn append(&mut self, other: Events) {
+        self.probe_changes.extend(other.probe_changes);
+        self.recordings.extend(other.recordings);
+        self.breakpoint_hits.extend(other.breakpoint_hits);
+    }
+}
+
+/// The result of [`PlotData::update`].
+pub struct Update {
+    /// When the circuit needs to be updated again, `None` once it is stopped
+    pub next_wake: Option<Instant>,
+    pub lag_warning: Option<String>,
+    pub lowered_rtps: bool,
+    pub events: Events,
+}
+
+/// A compiled circuit, shared between [`crate::PluginData`] and the tick loop.
+pub struct PlotData {
+    /// The circuit as it was compiled, see [`PlotData::saved_info`] for the current tick rate
+    pub info: CircuitInfo,
+    pub persist: bool,
+    /// See [`config::CompileFlags::flush_rate`]
+    flush_rate: Option<f64>,
+    /// See [`config::CompileFlags::render_io_only`], stepping still sends every change
+    render_io_only: bool,
+    /// The graph to write to the data folder when compiled with `--export-dot`, see
+    /// [`PlotData::export_dot`]
+    dot_export: Option<Result<String, String>>,
+    /// See [`PlotData::graph`]
+    graph: tokio::sync::OnceCell<Graph>,
+    pub world: Arc<pumpkin::world::World>,
+    pub base: mchprs_blocks::BlockPos,
+    pub plot: TestWorld,
+    /// Looked up when applying changes, instead of reading the block from the world every time
+    blocks: BlockCache,
+    inputs: mpsc::Sender<Input>,
+    /// See [`PlotData::rate`]
+    rate: Mutex<TickRate>,
+    sim: Mutex<Simulation>,
+    /// The block state last written to the world at each local position, starting with the states
+    /// read when compiling. Blocks players change are dropped with [`PlotData::forget_applied`].
+    /// It is locked before
+    /// flushing and held until the changes are applied, so they reach the world in the order they
+    /// were flushed in.
+    applied: tokio::sync::Mutex<HashMap<mchprs_blocks::BlockPos, u16>>,
+}
+
+impl PlotData {
+    pub async fn compile(
+        world: Arc<pumpkin::world::World>,
+        info: CircuitInfo,
+    ) -> Result<Self, String> {
+        let flags = config::parse_compile_flags(&info.flags)?;
+        let p1 = array_to_pos(info.pos1);
+        let p2 = array_to_pos(info.pos2);
+
+        let (mut plot, blocks, states) = compile::read_region(&world, p1, p2).await;
+        let options = flags.options;
+        // Compiling a large circuit takes a while, and exporting the graph waits for other exports
+        let (plot, compiler, dot) = tokio::task::spawn_blocking(move || {
+            if options.export_dot_graph {
+                let (compiler, dot) = compile::compile_world_with_graph(&mut plot, options);
+                (plot, compiler, Some(dot))
+            } else {
+                let compiler = compile::compile_world(&mut plot, options);
+                (plot, compiler, None)
+            }
+        })
+        .await
+        .map_err(|_| format!("The compiler failed on circuit {}", info.name))?;
+        // The exported graph is kept for `/rp inspect`, so it doesn't compile again
+        let graph = dot
+            .as_ref()
+            .and_then(|dot| dot.as_deref().ok())
+            .map(Graph::parse);
+
+        let (inputs, receiver) = mpsc::channel();
+        let now = Instant::now();
+
+        let base = mchprs_blocks::BlockPos::new(
+            p1.0.x.min(p2.0.x),
+            p1.0.y.min(p2.0.y),
+            p1.0.z.min(p2.0.z),
+        );
+
+        Ok(PlotData {
+            base,
+            sim: Mutex::new(Simulation {
+                compiler,
+                clock: TickClock::new(now),
+                lag: LagMonitor::new(now),
+                dirty: false,
+                next_flush: now,
+                inputs: receiver,
+                stopped: false,
+                paused: false,
+                probes: Vec::new(),
+                recording: None,
+                breakpoints: Vec::new(),
+                tick_count: 0,
+                pending: PumpkinWorld::new(base),
+                hidden: HashMap::new(),
+                events: Events::default(),
+            }),
+            rate: Mutex::new(info.rate),
+            info,
+            persist: flags.persist,
+            flush_rate: flags.flush_rate,
+            render_io_only: flags.render_io_only,
+            dot_export: dot,
+            graph: tokio::sync::OnceCell::new_with(graph),
+            world,
+            plot,
+            blocks,
+            inputs,
+            applied: tokio::sync::Mutex::new(states),
+        })
+    }
+
+    pub fn sim(&self) -> MutexGuard<'_, Simulation> {
+        // A panic in the compiler leaves nothing half updated that we rely on
+        self.sim.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The tick rate, kept out of the simulation so reading or changing it never waits for
+    /// running ticks.
+    pub fn rate(&self) -> MutexGuard<'_, TickRate> {
+        self.rate.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn send_input(&self, input: Input) {
+        // The receiver lives as long as the circuit, so this can't fail
+        let _ = self.inputs.send(input);
+    }
+
+    /// The info to save, with the current tick rate and without one shot compile flags.
+    pub fn saved_info(&self) -> CircuitInfo {
+        CircuitInfo {
+            flags: config::persistent_flags(&self.info.flags),
+            rate: *self.rate(),
+            ..self.info.clone()
+        }
+    }
+
+    /// Writes the graph into `data_folder` the first time this is called, when the circuit was
+    /// compiled with `--export-dot`.
+    pub fn export_dot(&mut self, data_folder: &Path) -> Option<Result<DotExport, String>> {
+        let dot = self.dot_export.take()?;
+        Some(dot.and_then(|dot| export::write_dot_export(data_folder, &self.info.name, &dot)))
+    }
+
+    pub fn bounds(&self) -> (mchprs_blocks::BlockPos, mchprs_blocks::BlockPos) {
+        (
+            mchprs_blocks::BlockPos::new(0, 0, 0),
+            mchprs_blocks::BlockPos::new(
+                self.plot.size_x - 1,
+                self.plot.size_y - 1,
+                self.plot.size_z - 1,
+            ),
+        )
+    }
+
+    pub fn to_local(&self, pos: BlockPos) -> mchprs_blocks::BlockPos {
+        mchprs_blocks::BlockPos::new(
+            pos.0.x - self.base.x,
+            pos.0.y - self.base.y,
+            pos.0.z - self.base.z,
+        )
+    }
+
+    pub fn contains(&self, world: &pumpkin::world::World, pos: BlockPos) -> bool {
+        self.info.world == world.get_world_name() && self.plot.block_in_world(self.to_local(pos))
+    }
+
+    /// Whether `pos` is in the circuit or next to it, so a block placed against it may be inside.
+    pub fn touches(&self, world: &pumpkin::world::World, pos: BlockPos) -> bool {
+        let local = self.to_local(pos);
+        let (min, max) = self.bounds();
+
+        self.info.world == world.get_world_name()
+            && (min.x - 1..=max.x + 1).contains(&local.x)
+            && (min.y - 1..=max.y + 1).contains(&local.y)
+            && (min.z - 1..=max.z + 1).contains(&local.z)
+    }
+
+    pub fn overlaps(&self, world: &pumpkin::world::World, p1: BlockPos, p2: BlockPos) -> bool {
+        let [ax1, ay1, az1] = self.info.pos1;
+        let [ax2, ay2, az2] = self.info.pos2;
+
+        self.info.world == world.get_world_name()
+            && ax1.min(ax2) <= p1.0.x.max(p2.0.x)
+            && ax1.max(ax2) >= p1.0.x.min(p2.0.x)
+            && ay1.min(ay2) <= p1.0.y.max(p2.0.y)
+            && ay1.max(ay2) >= p1.0.y.min(p2.0.y)
+            && az1.min(az2) <= p1.0.z.max(p2.0.z)
+            && az1.max(az2) >= p1.0.z.min(p2.0.z)
+    }
+
+    /// Handles inputs, runs the ticks that are due and flushes changes to the world when needed.
+    pub async fn update(&self, now: Instant, config: &Config) -> Update {
+        let rate = *self.rate();
+        let world_age = match rate {
+            // Plugins don't get a server tick event, so the world age is polled instead
+            TickRate::Sync => Some(self.world.level_time.lock().await.world_age),
+            TickRate::Rtps(_) | TickRate::Unlimited => None,
+        };
+
+        let mut update = Update {
+            next_wake: None,
+            lag_warning: None,
+            lowered_rtps: false,
+            events: Events::default(),
+        };
+        // A command is writing this circuit to the world, so try again right away instead of
+        // keeping the other circuits waiting
+        let Ok(mut applied) = self.applied.try_lock() else {
+            update.next_wake = Some(now);
+            return update;
+        };
+
+        let (ticks_due, max_ticks) = {
+            let mut sim = self.sim();
+            if sim.stopped {
+                return update;
+            }
+
+            // Inputs are still handled while paused, so levers can be flipped between steps
+            sim.handle_inputs();
+
+            match (rate, world_age) {
+                _ if sim.paused => {
+                    sim.clock.restart(now);
+                    (0, 0)
+                }
+                (TickRate::Unlimited, _) => {
+                    // Tick as fast as possible, flushing still happens at the flush rate
+                    sim.clock.restart(now);
+                    (0, u64::MAX)
+                }
+                (rate, world_age) => {
+                    let ticks_due = match (rate, world_age) {
+                        (TickRate::Sync, Some(world_age)) => {
+                            sim.clock.advance_synced(now, world_age)
+                        }
+                        (rate, _) => sim.clock.advance(now, rate.rtps()),
+                    };
+                    let max_ticks = ticks_due.min(config.max_ticks_per_update(rate.rtps()));
+                    (ticks_due, max_ticks)
+                }
+            }
+        };
+
+        let time_slice =
+            matches!(rate, TickRate::Unlimited).then(|| Instant::now() + UNLIMITED_TIME_SLICE);
+        let ticks = self.run_ticks(max_ticks, time_slice);
+
+        let mut changes = None;
+        {
+            let mut sim = self.sim();
+            if sim.stopped {
+                return update;
+            }
+
+            let sim = &mut *sim;
+            // Ticks left over because the circuit was paused in the meantime are not lag, and
+            // unlimited circuits can't fall behind
+            let ticks_due = match rate {
+                TickRate::Unlimited => ticks,
+                _ if sim.paused => ticks,
+                TickRate::Rtps(_) | TickRate::Sync => ticks_due,
+            };
+            if ticks > 0 {
+                sim.dirty = true;
+            }
+
+            if let Some(report) = sim.lag.record(now, ticks_due, ticks) {
+                let mut warning = format!(
+                    "Circuit {} can't keep up, it ran at {:.1} rtps instead of {:.1} rtps",
+                    self.info.name, report.measured_rtps, report.target_rtps
+                );
+
+                if let (LagPolicy::LowerRtps, TickRate::Rtps(rtps)) =
+                    (config.lag_policy, &mut *self.rate())
+                {
+                    *rtps = config.clamp_rtps((report.measured_rtps * 0.9).floor());
+                    warning += &format!(", lowered its rtps to {rtps}");
+                    update.lowered_rtps = true;
+                }
+
+                log::warn!("{warning}");
+                if sim.lag.should_warn(now) {
+                    update.lag_warning = Some(warning);
+                }
+            }
+
+            // Ticks and world updates run at separate rates
+            if sim.dirty && now >= sim.next_flush {
+                let mut world = sim.flush();
+                if self.render_io_only {
+                    world.retain_io(&mut sim.hidden);
+                }
+                changes = Some(world);
+                sim.dirty = false;
+                sim.next_flush = now + config.flush_interval(self.flush_rate);
+            }
+
+            let wake = match rate {
+                _ if sim.paused => None,
+                TickRate::Rtps(rtps) => Some(sim.clock.next_tick(rtps)),
+                TickRate::Sync => Some(now + SYNC_POLL_INTERVAL),
+                TickRate::Unlimited => Some(now),
+            };
+            update.next_wake = match wake {
+                Some(wake) if sim.dirty => Some(wake.min(sim.next_flush)),
+                None if sim.dirty => Some(sim.next_flush),
+                wake => wake,
+            };
+            update.events = std::mem::take(&mut sim.events);
+        }
+
+        if let Some(changes) = changes {
+            self.apply(&mut applied, changes).await;
+        }
+
+        update
+    }
+
+    /// Runs up to `ticks` ticks in batches of [`TICK_BATCH`], releasing the simulation lock in
+    /// between. Stops early once `deadline` passed or when the circuit was paused or stopped in
+    /// the meantime, returning the number of ticks that ran.
+    fn run_ticks(&self, ticks: u64, deadline: Option<Instant>) -> u64 {
+        let mut ran = 0;
+        while ran < ticks && deadline.is_none_or(|deadline| Instant::now() < deadline) {
+            let mut sim = self.sim();
+            if sim.stopped || sim.paused {
+                break;
+            }
+            ran += sim.tick((ticks - ran).min(TICK_BATCH));
+        }
+        ran
+    }
+
+    pub fn pause(&self) {
+        self.sim().paused = true;
+    }
+
+    pub fn resume(&self) {
+        let mut sim = self.sim();
+        sim.paused = false;
+        // Don't catch up on the ticks that would have run while paused
+        sim.clock.restart(Instant::now());
+    }
+
+    /// Pauses the circuit and runs `ticks` ticks, or fewer when a breakpoint is hit, then writes
+    /// the result to the world.
+    ///
+    /// The ticks run on a blocking thread in batches of [`TICK_BATCH`], as a large step takes a
+    /// while. Returns the number of ticks that ran and what happened during them.
+    pub async fn step(self: Arc<Self>, ticks: u64) -> (u64, Events) {
+        let mut applied = self.applied.lock().await;
+        let plot = self.clone();
+        let stepped = tokio::task::spawn_blocking(move || {
+            {
+                let mut sim = plot.sim();
+                if sim.stopped {
+                    return None;
+                }
+                sim.paused = true;
+                sim.handle_inputs();
+            }
+
+            let mut stepped = 0;
+            let mut events = Events::default();
+            while stepped < ticks && events.breakpoint_hits.is_empty() {
+                let mut sim = plot.sim();
+                if sim.stopped {
+                    return None;
+                }
+                stepped += sim.tick((ticks - stepped).min(TICK_BATCH));
+                events.append(std::mem::take(&mut sim.events));
+            }
+
+            let mut sim = plot.sim();
+            if sim.stopped {
+                return None;
+            }
+            let sim = &mut *sim;
+            let mut world = sim.flush();
+            world.restore_hidden(&mut sim.hidden);
+            sim.dirty = false;
+            events.append(std::mem::take(&mut sim.events));
+            Some((stepped, world, events))
+        })
+        .await;
+
+        // The task only fails when the compiler panicked
+        let Ok(Some((ticks, world, events))) = stepped else {
+            return (0, Events::default());
+        };
+        self.apply(&mut applied, world).await;
+        (ticks, events)
+    }
+
+    /// Adds a probe, replacing the probe with the same name.
+    pub fn add_probe(&self, probe: Probe) {
+        let mut sim = self.sim();
+        sim.probes.retain(|other| other.name != probe.name);
+        sim.probes.push(probe);
+    }
+
+    /// Removes the probe named `name` and its breakpoints, returning whether there was one.
+    pub fn remove_probe(&self, name: &str) -> bool {
+        let mut sim = self.sim();
+        let count = sim.probes.len();
+        sim.probes.retain(|probe| probe.name != name);
+        sim.breakpoints
+            .retain(|breakpoint| breakpoint.probe != name);
+        sim.probes.len() != count
+    }
+
+    pub fn probes(&self) -> Vec<Probe> {
+        self.sim().probes.clone()
+    }
+
+    /// Records every probe for the next `ticks` ticks, replacing the current recording.
+    ///
+    /// Returns the number of probes recorded, fails when the circuit has none.
+    pub fn start_recording(&self, owner: Uuid, ticks: u64) -> Result<usize, String> {
+        let mut sim = self.sim();
+        let sim = &mut *sim;
+        if sim.probes.is_empty() {
+            return Err(format!(
+                "Circuit {} has no probes to record, add them with /rp probe add",
+                self.info.name
+            ));
+        }
+
+        // Changes made before the recording starts are not part of it
+        sim.compiler.flush(&mut sim.pending);
+        sim.recording = Some(Recording::new(owner, &sim.probes, &sim.pending, ticks));
+        Ok(sim.probes.len())
+    }
+
+    /// Stops recording before the end of the window, returning what was recorded so far.
+    pub fn stop_recording(&self) -> Option<Recording> {
+        self.sim().recording.take()
+    }
+
+    /// Pauses the circuit when the probe named `probe` meets `condition`, returns false when there
+    /// is no such probe.
+    pub fn add_breakpoint(&self, probe: &str, owner: Uuid, condition: Condition) -> bool {
+        let mut sim = self.sim();
+        let sim = &mut *sim;
+        let Some(probe) = sim.probes.iter().find(|other| other.name == probe) else {
+            return false;
+        };
+
+        // Changes made before the breakpoint is set don't trigger it
+        sim.compiler.flush(&mut sim.pending);
+        let breakpoint = Breakpoint::new(probe, owner, condition, &sim.pending);
+        sim.breakpoints.push(breakpoint);
+        true
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.sim().breakpoints.clone()
+    }
+
+    /// Removes every breakpoint, returning how many there were.
+    pub fn clear_breakpoints(&self) -> usize {
+        std::mem::take(&mut self.sim().breakpoints).len()
+    }
+
+    /// The power level at `pos` that was flushed from the compiler but is not applied to the world
+    /// yet, `None` when the world is up to date.
+    pub fn unapplied_power(&self, pos: mchprs_blocks::BlockPos) -> Option<u8> {
+        let mut sim = self.sim();
+        let sim = &mut *sim;
+        sim.compiler.flush(&mut sim.pending);
+        probe::flushed_power(&sim.pending, pos)
+    }
+
+    /// The graph the circuit was compiled into. Unless it was compiled with `--export-dot`, the
+    /// blocks it was compiled from are compiled again on a blocking thread the first time this is
+    /// needed.
+    pub async fn graph(self: &Arc<Self>) -> Result<&Graph, String> {
+        self.graph
+            .get_or_try_init(|| async {
+                let options = config::parse_compile_flags(&self.info.flags)?.options;
+                let plot = self.clone();
+                let (_, dot) = tokio::task::spawn_blocking(move || {
+                    let mut world = plot.plot.copy();
+                    compile::compile_world_with_graph(&mut world, options)
+                })
+                .await
+                .map_err(|_| format!("The compiler failed on circuit {}", self.info.name))?;
+
+                Ok(Graph::parse(&dot?))
+            })
+            .await
+    }
+
+    /// Stops the tick loop from touching this circuit, without writing its state to the world.
+    pub fn stop(&self) {
+        self.sim().stopped = true;
+    }
+
+    /// Writes the current state of the compiled circuit back into the Pumpkin world.
+    pub async fn reset(&self) {
+        let mut applied = self.applied.lock().await;
+        let world = {
+            let mut sim = self.sim();
+            if sim.stopped {
+                return;
+            }
+            sim.stopped = true;
+            let bounds = self.bounds();
+            let sim = &mut *sim;
+            let mut world = sim.flush();
+            world.restore_hidden(&mut sim.hidden);
+            sim.compiler.reset(&mut world, bounds);
+            world
+        };
+        self.apply(&mut applied, world).await;
+    }
+
+    /// Writes flushed changes to the world, `applied` must be locked from before they were
+    /// flushed.
+    async fn apply(
+        &self,
+        applied: &mut HashMap<mchprs_blocks::BlockPos, u16>,
+        mut changes: PumpkinWorld,
+    ) {
+        changes
+            .apply(self.world.clone(), &self.blocks, applied)
+            .await;
+    }
+
+    /// Whether the block at `pos` can be used, see [`pumpkin_plot::is_input_block`].
+    pub fn is_input(&self, pos: mchprs_blocks::BlockPos) -> bool {
+        pumpkin_plot::is_input_block(self.plot.get_block(pos))
+    }
+
+    /// Drops the state last written to `pos` when something else changed the block, so the next
+    /// flush writes it again instead of skipping it.
+    pub async fn forget_applied(&self, pos: BlockPos) {
+        self.applied.lock().await.remove(&self.to_local(pos));
+    }
+
+    /// Why the compiler made no nodes for the block at `pos`, see [`inspect::without_nodes`].
+    pub fn without_nodes(&self, pos: mchprs_blocks::BlockPos) -> &'static str {
+        let optimize =
+            config::parse_compile_flags(&self.info.flags).is_ok_and(|flags| flags.options.optimize);
+        inspect::without_nodes(self.plot.get_block(pos), optimize)
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, PoisonError, atomic::Ordering},
+};
+
+use mchprs_blocks::blocks::{
+    Lever, RedstoneComparator, RedstoneRepeater, RedstoneWire, RedstoneWireSide,
+};
+use mchprs_redpiler::{Compiler, CompilerOptions};
+use mchprs_world::World;
+use pumpkin_data::{
+    Block, BlockState,
+    block_properties::{
+        self, BlockProperties, ComparatorLikeProperties, EastWireConnection, EnumVariants,
+        HorizontalFacing, LeverLikeProperties, NorthWireConnection, OakTrapdoorLikeProperties,
+        RepeaterLikeProperties, SouthWireConnection, StonePressurePlateLikeProperties,
+        WestWireConnection,
+    },
+    item::Item,
+};
+use pumpkin_util::math::position::BlockPos;
+use pumpkin_world::block::entities::{
+    barrel::BarrelBlockEntity, comparator::ComparatorBlockEntity,
+};
+
+use crate::{
+    RTorchProps, RWallTorchProps, RedstoneLampProperties, RedstoneWireProperties, export,
+    fixed_world::TestWorld, pumpkin_plot::BlockCache,
+};
+
+const AIR: u16 = Block::AIR.id;
+const LEVER: u16 = Block::LEVER.id;
+const STONE_BUTTON: u16 = Block::STONE_BUTTON.id;
+const STONE_PRESSURE_PLATE: u16 = Block::STONE_PRESSURE_PLATE.id;
+const REDSTONE_BLOCK: u16 = Block::REDSTONE_BLOCK.id;
+
+const REDSTONE_LAMP: u16 = Block::REDSTONE_LAMP.id;
+const IRON_TRAPDOOR: u16 = Block::IRON_TRAPDOOR.id;
+
+const REDSTONE_WIRE: u16 = Block::REDSTONE_WIRE.id;
+const REDSTONE_TORCH: u16 = Block::REDSTONE_TORCH.id;
+const REDSTONE_WALL_TORCH: u16 = Block::REDSTONE_WALL_TORCH.id;
+
+const REPEATER: u16 = Block::REPEATER.id;
+
+const COMPARATOR: u16 = Block::COMPARATOR.id;
+const BARREL: u16 = Block::BARREL.id;
+
+const TARGET: u16 = Block::TARGET.id;
+
+/// A block that is not air, found by [`scan_region`].
+pub struct ScannedBlock {
+    pub pos: BlockPos,
+    /// Position relative to the lowest corner of the region
+    pub local: mchprs_blocks::BlockPos,
+    pub block: &'static Block,
+    pub state: &'static BlockState,
+}
+
+/// Reads all blocks between `p1` and `p2` (inclusive) that are not air, in x, z, y order.
+pub async fn scan_region(
+    world: &pumpkin::world::World,
+    p1: BlockPos,
+    p2: BlockPos,
+) -> Vec<ScannedBlock> {
+    let x1 = p1.0.x.min(p2.0.x);
+    let x2 = p1.0.x.max(p2.0.x);
+    let y1 = p1.0.y.min(p2.0.y);
+    let y2 = p1.0.y.max(p2.0.y);
+    let z1 = p1.0.z.min(p2.0.z);
+    let z2 = p1.0.z.max(p2.0.z);
+
+    let mut blocks = Vec::new();
+    for y in y1..=y2 {
+        for z in z1..=z2 {
+            for x in x1..=x2 {
+                let pos = BlockPos::new(x, y, z);
+                let (block, state) = world.get_block_and_state(&pos).await;
+                if block.id == AIR {
+                    continue;
+                }
+
+                blocks.push(ScannedBlock {
+                    pos,
+                    local: mchprs_blocks::BlockPos::new(x - x1, y - y1, z - z1),
+                    block,
+                    state,
+                });
+            }
+        }
+    }
+    blocks
+}
+
+/// Copies the blocks between `p1` and `p2` (inclusive) out of the Pumpkin world and compiles them.
+///
+/// Also returns the Pumpkin block at each local position, as those don't change while compiled.
+pub async fn compile_region(
+    world: &Arc<pumpkin::world::World>,
+    p1: BlockPos,
+    p2: BlockPos,
+    options: CompilerOptions,
+) -> (TestWorld, Compiler, BlockCache) {
+    let (mut plot, blocks, _) = read_region(world, p1, p2).await;
+    let compiler = compile_world(&mut plot, options);
+
+    (plot, compiler, blocks)
+}
+
+/// Copies the blocks between `p1` and `p2` (inclusive) out of the Pumpkin world, with the Pumpkin
+/// block and state at each local position.
+pub async fn read_region(
+    world: &Arc<pumpkin::world::World>,
+    p1: BlockPos,
+    p2: BlockPos,
+) -> (TestWorld, BlockCache, HashMap<mchprs_blocks::BlockPos, u16>) {
+    // TODO: Add all components including all containers
+    // TODO: Pass along pending ticks
+
+    let x1 = p1.0.x.min(p2.0.x);
+    let x2 = p1.0.x.max(p2.0.x);
+    let y1 = p1.0.y.min(p2.0.y);
+    let y2 = p1.0.y.max(p2.0.y);
+    let z1 = p1.0.z.min(p2.0.z);
+    let z2 = p1.0.z.max(p2.0.z);
+
+    let mut plot = TestWorld::new(x2 - x1 + 1, y2 - y1 + 1, z2 - z1 + 1);
+    let mut blocks = HashMap::new();
+    let mut states = HashMap::new();
+
+    for scanned in scan_region(world, p1, p2).await {
+        blocks.insert(scanned.local, scanned.block);
+        states.insert(scanned.local, scanned.state.id);
+        plot.set_block(scanned.local, to_mchprs_block(scanned.block, scanned.state));
+
+        if matches!(scanned.block.id, COMPARATOR | BARREL) {
+            if let Some(entity) = read_block_entity(world, scanned.pos).await {
+                plot.set_block_entity(scanned.local, entity);
+            }
+        }
+    }
+
+    (plot, blocks, states)
+}
+
+/// Compiles everything in `plot`, this doesn't need a Pumpkin world.
+///
+/// The graph is never exported here, see [`compile_world_with_graph`].
+pub fn compile_world(plot: &mut TestWorld, mut options: CompilerOptions) -> Compiler {
+    options.export_dot_graph = false;
+    run_compiler(plot, options)
+}
+
+/// Compiles everything in `plot` like [`compile_world`], also returning the graph the compiler
+/// exported.
+///
+/// This waits for other compiles that export their graph, so call it on a blocking thread.
+pub fn compile_world_with_graph(
+    plot: &mut TestWorld,
+    mut options: CompilerOptions,
+) -> (Compiler, Result<String, String>) {
+    options.export_dot_graph = true;
+    // The compiler always writes the graph to the same file, so only one compile may use it
+    let _dot_file = export::DOT_FILE_LOCK
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let compiler = run_compiler(plot, options);
+
+    (compiler, export::take_dot_graph())
+}
+
+fn run_compiler(plot: &mut TestWorld, options: CompilerOptions) -> Compiler {
+    let min_pos = mchprs_blocks::BlockPos::new(0, 0, 0);
+    let max_pos = mchprs_blocks::BlockPos::new(plot.size_x - 1, plot.size_y - 1, plot.size_z - 1);
+
+    let mut compiler = Compiler::default();
+    let bounds = (min_pos, max_pos);
+    let ticks = plot.to_be_ticked.drain(..).collect();
+    let monitor = Default::default();
+    compiler.compile(plot, bounds, options, ticks, monitor);
+
+    compiler
+}
+
+/// Maps a Pumpkin block state to the MCHPRS block, blocks the compiler doesn't know about become
+/// iron blocks or glass depending on whether they are solid.
+pub fn to_mchprs_block(b: &Block, s: &BlockState) -> mchprs_blocks::blocks::Block {
+    match b.id {
+        AIR => mchprs_blocks::blocks::Block::Air {},
+        REDSTONE_WIRE => {
+            let props = RedstoneWireProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneWire {
+                wire: RedstoneWire::new(
+                    match props.north {
+                        NorthWireConnection::Up => RedstoneWireSide::Up,
+                        NorthWireConnection::Side => RedstoneWireSide::Side,
+                        NorthWireConnection::None => RedstoneWireSide::None,
+                    },
+                    match props.south {
+                        SouthWireConnection::Up => RedstoneWireSide::Up,
+                        SouthWireConnection::Side => RedstoneWireSide::Side,
+                        SouthWireConnection::None => RedstoneWireSide::None,
+                    },
+                    match props.east {
+                        EastWireConnection::Up => RedstoneWireSide::Up,
+                        EastWireConnection::Side => RedstoneWireSide::Side,
+                        EastWireConnection::None => RedstoneWireSide::None,
+                    },
+                    match props.west {
+                        WestWireConnection::Up => RedstoneWireSide::Up,
+                        WestWireConnection::Side => RedstoneWireSide::Side,
+                        WestWireConnection::None => RedstoneWireSide::None,
+                    },
+                    props.power.to_index() as u8,
+                ),
+            }
+        }
+        STONE_BUTTON => {
+            let props = LeverLikeProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::StoneButton {
+                button: mchprs_blocks::blocks::StoneButton::new(
+                    match props.face {
+                        block_properties::BlockFace::Floor => {
+                            mchprs_blocks::blocks::ButtonFace::Floor
+                        }
+                        block_properties::BlockFace::Wall => {
+                            mchprs_blocks::blocks::ButtonFace::Wall
+                        }
+                        block_properties::BlockFace::Ceiling => {
+                            mchprs_blocks::blocks::ButtonFace::Ceiling
+                        }
+                    },
+                    direction_to_mchprs(props.facing),
+                    props.powered,
+                ),
+            }
+        }
+        LEVER => {
+            let props = LeverLikeProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::Lever {
+                lever: Lever::new(
+                    match props.face {
+                        block_properties::BlockFace::Floor => {
+                            mchprs_blocks::blocks::LeverFace::Floor
+                        }
+                        block_properties::BlockFace::Wall => mchprs_blocks::blocks::LeverFace::Wall,
+                        block_properties::BlockFace::Ceiling => {
+                            mchprs_blocks::blocks::LeverFace::Ceiling
+                        }
+                    },
+                    direction_to_mchprs(props.facing),
+                    props.powered,
+                ),
+            }
+        }
+        STONE_PRESSURE_PLATE => {
+            let props = StonePressurePlateLikeProperties::from_state_id(s.id, b);
+            mchprs_blocks::blocks::Block::StonePressurePlate {
+                powered: props.powered,
+            }
+        }
+        REDSTONE_BLOCK => mchprs_blocks::blocks::Block::RedstoneBlock {},
+        REDSTONE_LAMP => {
+            let props = RedstoneLampProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneLamp { lit: props.lit }
+        }
+        IRON_TRAPDOOR => {
+            let props = OakTrapdoorLikeProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::IronTrapdoor {
+                facing: direction_to_mchprs(props.facing),
+                half: match props.half {
+                    block_properties::BlockHalf::Top => mchprs_blocks::blocks::TrapdoorHalf::Top,
+                    block_properties::BlockHalf::Bottom => {
+                        mchprs_blocks::blocks::TrapdoorHalf::Bottom
+                    }
+                },
+                powered: props.powered,
+            }
+        }
+        REDSTONE_TORCH => {
+            let props = RTorchProps::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneTorch { lit: props.lit }
+        }
+        REDSTONE_WALL_TORCH => {
+            let props = RWallTorchProps::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneWallTorch {
+                lit: props.lit,
+                facing: direction_to_mchprs(props.facing),
+            }
+        }
+        REPEATER => {
+            let props = RepeaterLikeProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    delay: 1 + props.delay.to_index() as u8,
+                    facing: direction_to_mchprs(props.facing),
+                    locked: props.locked,
+                    powered: props.powered,
+                },
+            }
+        }
+        COMPARATOR => {
+            let props = ComparatorLikeProperties::from_state_id(s.id, b);
+
+            mchprs_blocks::blocks::Block::RedstoneComparator {
+                comparator: RedstoneComparator::new(
+                    direction_to_mchprs(props.facing),
+                    match props.mode {
+                        block_properties::ComparatorMode::Compare => {
+                            mchprs_blocks::blocks::ComparatorMode::Compare
+                        }
+                        block_properties::ComparatorMode::Subtract => {
+                            mchprs_blocks::blocks::ComparatorMode::Subtract
+                        }
+                    },
+                    props.powered,
+                ),
+            }
+        }
+        BARREL => mchprs_blocks::blocks::Block::Barrel {},
+        TARGET => mchprs_blocks::blocks::Block::Target {},
+        _ => {
+            if let Some(block) = mchprs_blocks::blocks::Block::from_name(b.name) {
+                block
+            } else {
+                let solid = s.is_solid();
+
+                // sender.send_message(TextComponent::text(format!("Unknown block {:?}", solid))).await;
+
+                if solid {
+                    mchprs_blocks::blocks::Block::IronBlock {}
+                } else {
+                    mchprs_blocks::blocks::Block::Glass {}
+                }
+            }
+        }
+    }
+}
+
+/// Reads the comparator output or container fullness the compiler needs for the block at `pos`.
+pub async fn read_block_entity(
+    world: &pumpkin::world::World,
+    pos: BlockPos,
+) -> Option<mchprs_blocks::block_entities::BlockEntity> {
+    let entity = world.get_block_entity(&pos).await?;
+
+    if let Some(entity) = entity.as_any().downcast_ref::<ComparatorBlockEntity>() {
+        return Some(mchprs_blocks::block_entities::BlockEntity::Comparator {
+            output_strength: entity.output_signal.load(Ordering::Relaxed),
+        });
+    }
+
+    if let Some(entity) = entity.as_any().downcast_ref::<BarrelBlockEntity>() {
+        let mut fullness_sum: f32 = 0.0;
+        for slot in &entity.items {
+            let slot = slot.lock().await;
+            let count = slot.item_count;
+            if count == 0 {
+                continue;
+            }
+
+            fullness_sum += count as f32 / max_stack_size(slot.item) as f32;
+        }
+
+        return Some(mchprs_blocks::block_entities::BlockEntity::Container {
+            comparator_override: comparator_override(fullness_sum, entity.items.len()),
+            // TODO: fill inventory
+            inventory: Vec::new(),
+            ty: mchprs_blocks::block_entities::ContainerType::Barrel,
+        });
+    }
+
+    None
+}
+
+pub fn max_stack_size(item: &Item) -> u8 {
+    let mut max_stack_size = 64;
+    for component in item.components {
+        if component.0 == pumpkin_data::data_component::DataComponent::MaxStackSize {
+            if let Some(size) = component
+                .1
+                .as_any()
+                .downcast_ref::<pumpkin_data::data_component_impl::MaxStackSizeImpl>(
+            ) {
+                max_stack_size = size.size;
+            }
+        }
+    }
+    max_stack_size
+}
+
+/// The signal strength a comparator reads from a container, where `fullness_sum` is the sum of
+/// the item count divided by the max stack size of every slot.
+pub fn comparator_override(fullness_sum: f32, num_slots: usize) -> u8 {
+    (if fullness_sum > 0.0 { 1.0 } else { 0.0 } + (fullness_sum / num_slots as f32) * 14.0).floor()
+        as u8
+}
+
+fn direction_to_mchprs(face: HorizontalFacing) -> mchprs_blocks::BlockDirection {
+    match face {
+        HorizontalFacing::North => mchprs_blocks::BlockDirection::North,
+        HorizontalFacing::East => mchprs_blocks::BlockDirection::East,
+        HorizontalFacing::South => mchprs_blocks::BlockDirection::South,
+        HorizontalFacing::West => mchprs_blocks::BlockDirection::West,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(block: &'static Block, state: u16) -> mchprs_blocks::blocks::Block {
+        to_mchprs_block(block, BlockState::from_id(state))
+    }
+
+    #[test]
+    fn maps_air() {
+        assert_eq!(
+            map(&Block::AIR, Block::AIR.default_state.id),
+            mchprs_blocks::blocks::Block::Air {}
+        );
+    }
+
+    #[test]
+    fn maps_repeater() {
+        let state = RepeaterLikeProperties {
+            delay: block_properties::Integer1To4::from_index(2),
+            facing: HorizontalFacing::East,
+            locked: true,
+            powered: false,
+        }
+        .to_state_id(&Block::REPEATER);
+
+        assert_eq!(
+            map(&Block::REPEATER, state),
+            mchprs_blocks::blocks::Block::RedstoneRepeater {
+                repeater: RedstoneRepeater {
+                    delay: 3,
+                    facing: mchprs_blocks::BlockDirection::East,
+                    locked: true,
+                    powered: false,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn maps_wall_lever() {
+        let state = LeverLikeProperties {
+            face: block_properties::BlockFace::Wall,
+            facing: HorizontalFacing::South,
+            powered: true,
+        }
+        .to_state_id(&Block::LEVER);
+
+        assert_eq!(
+            map(&Block::LEVER, state),
+            mchprs_blocks::blocks::Block::Lever {
+                lever: Lever::new(
+                    mchprs_blocks::blocks::LeverFace::Wall,
+                    mchprs_blocks::BlockDirection::South,
+                    true,
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn maps_lit_lamp() {
+        let state = RedstoneLampProperties { lit: true }.to_state_id(&Block::REDSTONE_LAMP);
+
+        assert_eq!(
+            map(&Block::REDSTONE_LAMP, state),
+            mchprs_blocks::blocks::Block::RedstoneLamp { lit: true }
+        );
+    }
+
+    #[test]
+    fn comparator_override_of_barrel() {
+        assert_eq!(comparator_override(0.0, 27), 0);
+        assert_eq!(comparator_override(1.0 / 64.0, 27), 1);
+        assert_eq!(comparator_override(27.0, 27), 15);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{ops::RangeInclusive, path::Path, time::Duration};
+
+use mchprs_redpiler::{BackendVariant, CompilerOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler::{LagPolicy, TickRate};
+
+const CONFIG_FILE: &str = "config.toml";
+/// Compile flags that only apply when a player compiles a circuit, not when it is compiled again
+/// after a restart.
+const ONE_SHOT_FLAGS: [&str; 1] = ["--export-dot"];
+/// The rtps range in the config must be within these limits, slower rates put the next tick
+/// further away than a `Duration` can hold.
+const RTPS_LIMITS: RangeInclusive<f64> = 0.001..=1_000_000_000.0;
+/// Flush rates must be within these limits, for the same reason as [`RTPS_LIMITS`]. Clients
+/// can't show more than this anyway.
+const FLUSH_RATE_LIMITS: RangeInclusive<f64> = 0.001..=1000.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The rtps newly compiled circuits start with
+    pub default_rtps: f64,
+    pub min_rtps: f64,
+    pub max_rtps: f64,
+    /// Tick rates above this value require the `redpiler:rtps.high` permission
+    pub high_rtps_threshold: f64,
+    /// How many times per second changes of compiled circuits are sent to the world, unless the
+    /// circuit was compiled with `--flush-rate`
+    pub flush_rate: f64,
+    /// What to do when a circuit can't run all of its ticks in time
+    pub lag_policy: LagPolicy,
+    /// With the `catch_up` lag policy, the most ticks a circuit runs at once on top of its rtps
+    pub max_catch_up_ticks: u64,
+    /// Flags that are always passed to the compiler, see [`parse_compile_flags`]
+    pub compile_flags: String,
+    /// The largest number of blocks that can be compiled at once
+    pub max_selection_volume: u64,
+    /// Item that selects pos1 by left clicking and pos2 by right clicking a block
+    pub wand_item: String,
+    /// Whether placing, breaking or right clicking blocks in a circuit resets the compiled circuit
+    pub auto_invalidate: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            default_rtps: 10.0,
+            min_rtps: 0.1,
+            max_rtps: 10_000_000.0,
+            high_rtps_threshold: 10_000.0,
+            flush_rate: 20.0,
+            lag_policy: LagPolicy::CatchUp,
+            max_catch_up_ticks: 1000,
+            compile_flags: "--wire-dot-out".to_string(),
+            max_selection_volume: 256 * 256 * 256,
+            wand_item: "minecraft:wooden_axe".to_string(),
+            auto_invalidate: true,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from the plugin data folder, writing the defaults if it does not exist yet.
+    pub fn load(data_folder: &Path) -> Result<Config, String> {
+        let path = data_folder.join(CONFIG_FILE);
+
+        if !path.exists() {
+            let config = Config::default();
+            let contents = toml::to_string_pretty(&config)
+                .map_err(|err| format!("Failed to serialize default config: {err}"))?;
+            std::fs::create_dir_all(data_folder)
+                .and_then(|_| std::fs::write(&path, contents))
+                .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+            log::info!("Wrote default redpiler config to {}", path.display());
+            return Ok(config);
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        let config: Config = toml::from_str(&contents)
+            .map_err(|err| format!("Failed to parse {}: {err}", path.display()))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(RTPS_LIMITS.contains(&self.min_rtps)
+            && RTPS_LIMITS.contains(&self.max_rtps)
+            && self.min_rtps <= self.max_rtps)
+        {
+            return Err(format!(
+                "Invalid rtps range {}..={}, it must be within {}..={}",
+                self.min_rtps,
+                self.max_rtps,
+                RTPS_LIMITS.start(),
+                RTPS_LIMITS.end()
+            ));
+        }
+        if !(self.min_rtps..=self.max_rtps).contains(&self.default_rtps) {
+            return Err(format!(
+                "Default rtps {} is outside of the rtps range {}..={}",
+                self.default_rtps, self.min_rtps, self.max_rtps
+            ));
+        }
+        if !FLUSH_RATE_LIMITS.contains(&self.flush_rate) {
+            return Err(invalid_flush_rate(self.flush_rate));
+        }
+        parse_compile_flags(&self.compile_flags)?;
+        Ok(())
+    }
+
+    pub fn clamp_rtps(&self, rtps: f64) -> f64 {
+        rtps.clamp(self.min_rtps, self.max_rtps)
+    }
+
+    /// Limits a rate that was not checked when it was set, like one read from a file, to the rtps
+    /// range.
+    pub fn clamp_rate(&self, rate: TickRate) -> TickRate {
+        match rate {
+            TickRate::Rtps(rtps) if rtps.is_nan() => TickRate::Rtps(self.default_rtps),
+            TickRate::Rtps(rtps) => TickRate::Rtps(self.clamp_rtps(rtps)),
+            TickRate::Sync | TickRate::Unlimited => rate,
+        }
+    }
+
+    /// The time between flushes of a circuit, `flush_rate` overrides the rate from the config.
+    pub fn flush_interval(&self, flush_rate: Option<f64>) -> Duration {
+        Duration::from_secs_f64(1.0 / flush_rate.unwrap_or(self.flush_rate))
+    }
+
+    /// The most ticks a circuit at `rtps` may run in one update according to the lag policy.
+    pub fn max_ticks_per_update(&self, rtps: f64) -> u64 {
+        // Without lag a circuit never runs more ticks at once than fit in one flush
+        let ticks_per_flush = (rtps / self.flush_rate).ceil().max(1.0) as u64;
+        match self.lag_policy {
+            LagPolicy::CatchUp => ticks_per_flush + self.max_catch_up_ticks,
+            LagPolicy::Skip | LagPolicy::LowerRtps => ticks_per_flush,
+        }
+    }
+
+    pub fn is_wand(&self, registry_key: &str) -> bool {
+        self.wand_item
+            .strip_prefix("minecraft:")
+            .unwrap_or(&self.wand_item)
+            == registry_key
+    }
+}
+
+pub struct CompileFlags {
+    pub options: CompilerOptions,
+    /// Name of the circuit, defaults to the name of the player compiling it
+    pub name: Option<String>,
+    /// Whether the circuit is compiled again after a restart
+    pub persist: bool,
+    /// Overrides the `flush_rate` from the config for this circuit
+    pub flush_rate: Option<f64>,
+    /// Only send changes of inputs and outputs like lamps to the world, not wires and repeaters
+    pub render_io_only: bool,
+}
+
+/// The flags without [`ONE_SHOT_FLAGS`], as they are saved with the circuit.
+pub fn persistent_flags(flags: &str) -> String {
+    flags
+        .split_whitespace()
+        .filter(|flag| !ONE_SHOT_FLAGS.contains(flag))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn parse_compile_flags(flags: &str) -> Result<CompileFlags, String> {
+    let mut options = CompilerOptions::default();
+    let mut name = None;
+    let mut persist = true;
+    let mut flush_rate = None;
+    let mut render_io_only = false;
+
+    for flag in flags.split_whitespace() {
+        match flag {
+            "-O" | "--optimize" => options.optimize = true,
+            "-I" | "--io-only" => options.io_only = true,
+            "--wire-dot-out" => options.wire_dot_out = true,
+            "--export-dot" => options.export_dot_graph = true,
+            "--backend=direct" => options.backend_variant = BackendVariant::Direct,
+            "--no-persist" => persist = false,
+            "--render-io-only" => render_io_only = true,
+            _ => {
+                if let Some(value) = flag.strip_prefix("--name=") {
+                    name = Some(value.to_string());
+                } else if let Some(value) = flag.strip_prefix("--flush-rate=") {
+                    match value.parse::<f64>() {
+                        Ok(rate) if FLUSH_RATE_LIMITS.contains(&rate) => flush_rate = Some(rate),
+                        _ => return Err(invalid_flush_rate(value)),
+                    }
+                } else {
+                    return Err(format!("Unknown compile flag {flag}"));
+                }
+            }
+        }
+    }
+
+    Ok(CompileFlags {
+        options,
+        name,
+        persist,
+        flush_rate,
+        render_io_only,
+    })
+}
+
+fn invalid_flush_rate(rate: impl std::fmt::Display) -> String {
+    format!(
+        "Invalid flush rate {rate}, it must be between {} and {}",
+        FLUSH_RATE_LIMITS.start(),
+        FLUSH_RATE_LIMITS.end()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_rates_that_overflow_the_tick_interval() {
+        assert!(parse_compile_flags("--flush-rate=1e-20").is_err());
+        assert!(parse_compile_flags("--flush-rate=NaN").is_err());
+        assert!(parse_compile_flags("--flush-rate=inf").is_err());
+        assert!(parse_compile_flags("--flush-rate=2.5").is_ok());
+
+        let config = Config {
+            min_rtps: 1e-20,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        let config = Config {
+            flush_rate: 1e-20,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(Config::default().validate().is_ok());
+    }
+}
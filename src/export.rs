@@ -0,0 +1,72 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Where the compiler writes the graph when `export_dot_graph` is set, relative to the working
+/// directory of the server.
+const COMPILER_DOT_FILE: &str = "redpiler_graph.dot";
+
+/// Held from compiling with `export_dot_graph` until the graph is read back, as every compile
+/// writes to the same file.
+pub static DOT_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+pub struct DotExport {
+    pub path: PathBuf,
+    pub nodes: usize,
+    pub links: usize,
+}
+
+/// Reads and removes the graph the compiler just exported.
+pub fn take_dot_graph() -> Result<String, String> {
+    let contents = std::fs::read_to_string(COMPILER_DOT_FILE)
+        .map_err(|err| format!("Failed to read {COMPILER_DOT_FILE}: {err}"))?;
+    std::fs::remove_file(COMPILER_DOT_FILE)
+        .map_err(|err| format!("Failed to remove {COMPILER_DOT_FILE}: {err}"))?;
+    Ok(contents)
+}
+
+/// Writes a graph exported by the compiler into `data_folder`, named after the circuit and the
+/// current time.
+pub fn write_dot_export(
+    data_folder: &Path,
+    name: &str,
+    contents: &str,
+) -> Result<DotExport, String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = data_folder.join(format!("{}-{timestamp}.dot", file_name(name)));
+
+    std::fs::write(&path, contents)
+        .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+
+    // The graph is written with one node or link per line, like `0 [ label = ".." ]` and
+    // `0 -> 1 [ label = ".." ]`
+    let mut nodes = 0;
+    let mut links = 0;
+    for line in contents.lines().map(str::trim) {
+        if line.contains("->") {
+            links += 1;
+        } else if line.starts_with(|c: char| c.is_ascii_digit()) {
+            nodes += 1;
+        }
+    }
+
+    Ok(DotExport { path, nodes, links })
+}
+
+/// Replaces everything but letters, digits, `-` and `_` so names can't point outside the data
+/// folder.
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
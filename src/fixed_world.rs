@@ -1,20 +1,29 @@
 use mchprs_blocks::{BlockPos, block_entities::BlockEntity};
 use mchprs_world::{TickEntry, TickPriority, World, storage::Chunk};
 
+/// A world that only holds the blocks of one circuit, with its lowest corner at 0, 0, 0.
 pub struct TestWorld {
     chunks: Vec<Chunk>,
     pub to_be_ticked: Vec<TickEntry>,
+    /// Size in blocks
     pub size_x: i32,
     pub size_y: i32,
     pub size_z: i32,
+    chunks_x: i32,
+    chunks_z: i32,
 }
 
 impl TestWorld {
+    /// Creates an empty world of `size_x` by `size_y` by `size_z` blocks.
     pub fn new(size_x: i32, size_y: i32, size_z: i32) -> TestWorld {
+        let chunks_x = (size_x + 15) >> 4;
+        let chunks_z = (size_z + 15) >> 4;
+        let sections = (size_y + 15) >> 4;
+
         let mut chunks = Vec::new();
-        for x in 0..size_x {
-            for z in 0..size_z {
-                chunks.push(Chunk::empty(x, z, size_y as usize));
+        for x in 0..chunks_x {
+            for z in 0..chunks_z {
+                chunks.push(Chunk::empty(x, z, sections as usize));
             }
         }
         TestWorld {
@@ -23,33 +32,56 @@ impl TestWorld {
             size_x,
             size_y,
             size_z,
+            chunks_x,
+            chunks_z,
+        }
+    }
+
+    /// A copy of the blocks and block entities, without the ticks to be scheduled.
+    pub fn copy(&self) -> TestWorld {
+        let mut copy = TestWorld::new(self.size_x, self.size_y, self.size_z);
+        for y in 0..self.size_y {
+            for z in 0..self.size_z {
+                for x in 0..self.size_x {
+                    let pos = BlockPos::new(x, y, z);
+                    copy.set_block_raw(pos, self.get_block_raw(pos));
+                    if let Some(entity) = self.get_block_entity(pos) {
+                        copy.set_block_entity(pos, entity.clone());
+                    }
+                }
+            }
         }
+        copy
     }
 
     pub fn block_in_world(&self, pos: BlockPos) -> bool {
-        pos.x >= 0 && pos.x < self.size_x &&
-        pos.y >= 0 && pos.y < self.size_y &&
-        pos.z >= 0 && pos.z < self.size_z
+        pos.x >= 0
+            && pos.x < self.size_x
+            && pos.y >= 0
+            && pos.y < self.size_y
+            && pos.z >= 0
+            && pos.z < self.size_z
     }
 
-    fn get_chunk_index_for_chunk(&self, chunk_x: i32, chunk_z: i32) -> usize {
-        (chunk_x * self.size_z + chunk_z).unsigned_abs() as usize
+    fn get_chunk_index_for_chunk(&self, chunk_x: i32, chunk_z: i32) -> Option<usize> {
+        if chunk_x < 0 || chunk_z < 0 || chunk_x >= self.chunks_x || chunk_z >= self.chunks_z {
+            return None;
+        }
+        Some((chunk_x * self.chunks_z + chunk_z) as usize)
     }
 
-    fn get_chunk_index_for_block(&self, block_x: i32, block_z: i32) -> Option<usize> {
-        let chunk_x = block_x >> 4;
-        let chunk_z = block_z >> 4;
-        if chunk_x >= self.size_x || chunk_z >= self.size_z {
+    fn get_chunk_index_for_block(&self, pos: BlockPos) -> Option<usize> {
+        if !self.block_in_world(pos) {
             return None;
         }
-        Some(((chunk_x * self.size_z) + chunk_z).unsigned_abs() as usize)
+        self.get_chunk_index_for_chunk(pos.x >> 4, pos.z >> 4)
     }
 }
 
 impl World for TestWorld {
     /// Returns the block state id of the block at `pos`
     fn get_block_raw(&self, pos: BlockPos) -> u32 {
-        let chunk_index = match self.get_chunk_index_for_block(pos.x, pos.z) {
+        let chunk_index = match self.get_chunk_index_for_block(pos) {
             Some(idx) => idx,
             None => return 0,
         };
@@ -59,16 +91,11 @@ impl World for TestWorld {
 
     /// Sets a block in storage. Returns true if a block was changed.
     fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
-        let chunk_index = match self.get_chunk_index_for_block(pos.x, pos.z) {
+        let chunk_index = match self.get_chunk_index_for_block(pos) {
             Some(idx) => idx,
             None => return false,
         };
 
-        // Check to see if block is within height limit
-        if pos.y >= self.size_y * 16 || pos.y < 0 {
-            return false;
-        }
-
         let chunk = &mut self.chunks[chunk_index];
         chunk.set_block(
             (pos.x & 0xF) as u32,
@@ -79,7 +106,7 @@ impl World for TestWorld {
     }
 
     fn delete_block_entity(&mut self, pos: BlockPos) {
-        let chunk_index = match self.get_chunk_index_for_block(pos.x, pos.z) {
+        let chunk_index = match self.get_chunk_index_for_block(pos) {
             Some(idx) => idx,
             None => return,
         };
@@ -88,7 +115,7 @@ impl World for TestWorld {
     }
 
     fn get_block_entity(&self, pos: BlockPos) -> Option<&BlockEntity> {
-        let chunk_index = match self.get_chunk_index_for_block(pos.x, pos.z) {
+        let chunk_index = match self.get_chunk_index_for_block(pos) {
             Some(idx) => idx,
             None => return None,
         };
@@ -97,7 +124,7 @@ impl World for TestWorld {
     }
 
     fn set_block_entity(&mut self, pos: BlockPos, block_entity: BlockEntity) {
-        let chunk_index = match self.get_chunk_index_for_block(pos.x, pos.z) {
+        let chunk_index = match self.get_chunk_index_for_block(pos) {
             Some(idx) => idx,
             None => return,
         };
@@ -106,11 +133,11 @@ impl World for TestWorld {
     }
 
     fn get_chunk(&self, x: i32, z: i32) -> Option<&Chunk> {
-        self.chunks.get(self.get_chunk_index_for_chunk(x, z))
+        self.chunks.get(self.get_chunk_index_for_chunk(x, z)?)
     }
 
     fn get_chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut Chunk> {
-        let chunk_idx = self.get_chunk_index_for_chunk(x, z);
+        let chunk_idx = self.get_chunk_index_for_chunk(x, z)?;
         self.chunks.get_mut(chunk_idx)
     }
 
@@ -126,3 +153,57 @@ impl World for TestWorld {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mchprs_blocks::blocks::Block;
+
+    use super::*;
+
+    #[test]
+    fn stores_blocks_past_the_first_chunk() {
+        let mut world = TestWorld::new(20, 20, 20);
+        let pos = BlockPos::new(19, 19, 19);
+
+        assert!(world.set_block(pos, Block::RedstoneLamp { lit: true }));
+        assert_eq!(world.get_block(pos), Block::RedstoneLamp { lit: true });
+        assert_eq!(world.get_block(BlockPos::new(3, 19, 3)), Block::Air {});
+    }
+
+    #[test]
+    fn ignores_blocks_outside() {
+        let mut world = TestWorld::new(4, 4, 4);
+
+        for pos in [
+            BlockPos::new(4, 0, 0),
+            BlockPos::new(0, 4, 0),
+            BlockPos::new(0, 0, 4),
+            BlockPos::new(-1, 0, 0),
+            BlockPos::new(0, -1, 0),
+            BlockPos::new(0, 0, -1),
+        ] {
+            assert!(!world.block_in_world(pos));
+            assert!(!world.set_block(pos, Block::RedstoneBlock {}));
+            assert_eq!(world.get_block(pos), Block::Air {});
+        }
+        assert!(world.block_in_world(BlockPos::new(3, 3, 3)));
+    }
+
+    #[test]
+    fn copies_blocks_and_block_entities() {
+        let mut world = TestWorld::new(20, 2, 3);
+        let lamp = BlockPos::new(17, 1, 2);
+        let comparator = BlockPos::new(0, 0, 0);
+        world.set_block(lamp, Block::RedstoneLamp { lit: true });
+        world.set_block_entity(comparator, BlockEntity::Comparator { output_strength: 4 });
+
+        let copy = world.copy();
+
+        assert_eq!(copy.get_block(lamp), Block::RedstoneLamp { lit: true });
+        assert!(matches!(
+            copy.get_block_entity(comparator),
+            Some(BlockEntity::Comparator { output_strength: 4 })
+        ));
+        assert_eq!(copy.size_x, 20);
+    }
+}
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use mchprs_blocks::{BlockPos, blocks::Block};
+
+/// A node of the graph the compiler exports with `export_dot_graph`.
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    /// The node type with its settings, like `Repeater { delay: 2, facing_diode: false }`
+    pub ty: String,
+    /// Position relative to the lowest corner of the circuit
+    pub pos: Option<BlockPos>,
+    /// Output strength when the circuit was compiled
+    pub output_strength: Option<u8>,
+}
+
+/// A link from the output of one node to the input of another.
+#[derive(Debug, PartialEq)]
+pub struct Link {
+    pub from: usize,
+    pub to: usize,
+    /// Whether the link goes into the side of a repeater or comparator
+    pub side: bool,
+    /// How much the signal strength drops along the link, from wire between the nodes
+    pub distance: u8,
+}
+
+/// The graph of a compiled circuit, read back from its dot export.
+#[derive(Default)]
+pub struct Graph {
+    pub nodes: HashMap<usize, Node>,
+    pub links: Vec<Link>,
+}
+
+impl Graph {
+    /// Parses the graph written by the compiler, with one node or link per line like
+    /// `0 [ label = "CompileNode { ty: .., block: .., state: .. }" ]` and
+    /// `0 -> 1 [ label = "CompileLink { ty: Default, ss: 0 }" ]`.
+    ///
+    /// Lines that look different are skipped.
+    pub fn parse(dot: &str) -> Graph {
+        let mut graph = Graph::default();
+        for line in dot.lines().map(str::trim) {
+            let Some(label) = label(line) else {
+                continue;
+            };
+
+            let ids = line.split('[').next().unwrap_or_default();
+            match ids.split_once("->") {
+                Some((from, to)) => {
+                    let (Ok(from), Ok(to)) = (from.trim().parse(), to.trim().parse()) else {
+                        continue;
+                    };
+                    graph.links.push(Link {
+                        from,
+                        to,
+                        side: field(&label, "ty") == Some("Side"),
+                        distance: field(&label, "ss")
+                            .and_then(|ss| ss.parse().ok())
+                            .unwrap_or_default(),
+                    });
+                }
+                None => {
+                    let Ok(id) = ids.trim().parse() else {
+                        continue;
+                    };
+                    graph.nodes.insert(
+                        id,
+                        Node {
+                            ty: field(&label, "ty").unwrap_or("Unknown").to_string(),
+                            pos: block_pos(&label),
+                            output_strength: field(&label, "output_strength")
+                                .and_then(|strength| strength.parse().ok()),
+                        },
+                    );
+                }
+            }
+        }
+        graph
+    }
+
+    /// The nodes compiled from the block at `pos`, in order.
+    pub fn nodes_at(&self, pos: BlockPos) -> Vec<usize> {
+        let mut ids: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.pos == Some(pos))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The links into the node `id`.
+    pub fn inputs(&self, id: usize) -> impl Iterator<Item = &Link> {
+        self.links.iter().filter(move |link| link.to == id)
+    }
+
+    /// The number of links out of the node `id`.
+    pub fn output_count(&self, id: usize) -> usize {
+        self.links.iter().filter(|link| link.from == id).count()
+    }
+}
+
+/// Describes node `id` with its inputs, `base` is the lowest corner of the circuit in the world.
+///
+/// `power` is the current power level of the block, `None` when the world doesn't show it, like
+/// the output strength of a comparator. The one it was compiled with is shown then, as a hint.
+pub fn describe(graph: &Graph, id: usize, base: BlockPos, power: Option<u8>) -> String {
+    let Some(node) = graph.nodes.get(&id) else {
+        return format!("Node {id} is not part of the graph");
+    };
+    let to_world =
+        |pos: BlockPos| format!("{}, {}, {}", base.x + pos.x, base.y + pos.y, base.z + pos.z);
+
+    let power = match (power, node.output_strength) {
+        (Some(power), _) => power.to_string(),
+        (None, Some(compiled)) => format!("unknown, {compiled} when compiled"),
+        (None, None) => "unknown".to_string(),
+    };
+    let mut text = format!(
+        "Node {id}: {}, power {power}, {} outputs",
+        node.ty,
+        graph.output_count(id)
+    );
+    for link in graph.inputs(id) {
+        let kind = if link.side { "Side input" } else { "Input" };
+        let from = graph.nodes.get(&link.from);
+        let ty = from.map_or("Unknown", |node| node.ty.as_str());
+        let at = match from.and_then(|node| node.pos) {
+            Some(pos) => format!(" at {}", to_world(pos)),
+            None => String::new(),
+        };
+        text += &format!(
+            "\n  {kind} from node {} {ty}{at}, distance {}",
+            link.from, link.distance
+        );
+    }
+    text
+}
+
+/// Why a block has no nodes in the graph, for a circuit compiled with or without `optimize`.
+///
+/// Wire is never a node, the compiler turns it into the distance of the links it carries.
+pub fn without_nodes(block: Block, optimize: bool) -> &'static str {
+    let becomes_node = matches!(
+        block,
+        Block::RedstoneTorch { .. }
+            | Block::RedstoneWallTorch { .. }
+            | Block::RedstoneRepeater { .. }
+            | Block::RedstoneComparator { .. }
+            | Block::RedstoneBlock {}
+            | Block::RedstoneLamp { .. }
+            | Block::IronTrapdoor { .. }
+            | Block::NoteBlock { .. }
+            | Block::Lever { .. }
+            | Block::StoneButton { .. }
+            | Block::StonePressurePlate { .. }
+    );
+    match block {
+        Block::RedstoneWire { .. } => "is wire, which redpiler compiles into the links it carries",
+        _ if becomes_node && optimize => "was optimised away by redpiler",
+        _ if becomes_node => "was not compiled into a node",
+        _ => "is not a redstone component",
+    }
+}
+
+/// The label of a dot line, with the quotes escaped by the export removed.
+fn label(line: &str) -> Option<String> {
+    let start = line.find("label = \"")? + "label = \"".len();
+    let end = line.rfind('"')?;
+    (end > start).then(|| line[start..end].replace("\\\"", "\""))
+}
+
+/// The value of the first field called `name` in a `Debug` formatted struct, up to the `,` or `}`
+/// that ends it.
+fn field<'a>(label: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{name}: ");
+    let start = label
+        .match_indices(&pattern)
+        // Skip matches that only end in `name`, like `ty` in `empty`
+        .find(|&(index, _)| {
+            label[..index]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !c.is_alphanumeric() && c != '_')
+        })?
+        .0
+        + pattern.len();
+
+    let value = &label[start..];
+    let mut depth = 0;
+    for (index, c) in value.char_indices() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' if depth == 0 => return Some(value[..index].trim()),
+            '}' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => return Some(value[..index].trim()),
+            _ => {}
+        }
+    }
+    Some(value.trim())
+}
+
+fn block_pos(label: &str) -> Option<BlockPos> {
+    let start = label.find("BlockPos {")?;
+    let pos = &label[start..];
+    let coord = |name: &str| -> Option<i32> { field(pos, name)?.parse().ok() };
+    Some(BlockPos::new(coord("x")?, coord("y")?, coord("z")?))
+}
+
+#[cfg(test)]
+mod tests {
+    use mchprs_blocks::blocks::{RedstoneWire, RedstoneWireSide};
+
+    use super::*;
+
+    const DOT: &str = r#"digraph {
+    0 [ label = "CompileNode { ty: Torch, block: Some((BlockPos { x: 0, y: 1, z: 0 }, 5)), state: NodeState { powered: true, repeater_locked: false, output_strength: 15 }, is_input: false, is_output: false }" ]
+    1 [ label = "CompileNode { ty: Repeater { delay: 2, facing_diode: false }, block: Some((BlockPos { x: 3, y: 0, z: -1 }, 9)), state: NodeState { powered: false, repeater_locked: false, output_strength: 0 }, is_input: false, is_output: false }" ]
+    2 [ label = "CompileNode { ty: Constant, block: None, state: NodeState { powered: false, repeater_locked: false, output_strength: 7 }, is_input: false, is_output: false }" ]
+    0 -> 1 [ label = "CompileLink { ty: Default, ss: 2 }" ]
+    2 -> 1 [ label = "CompileLink { ty: Side, ss: 0 }" ]
+}
+"#;
+
+    #[test]
+    fn parses_nodes() {
+        let graph = Graph::parse(DOT);
+
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(
+            graph.nodes[&1],
+            Node {
+                ty: "Repeater { delay: 2, facing_diode: false }".to_string(),
+                pos: Some(BlockPos::new(3, 0, -1)),
+                output_strength: Some(0),
+            }
+        );
+        assert_eq!(graph.nodes[&0].output_strength, Some(15));
+        assert_eq!(graph.nodes[&2].pos, None);
+        assert_eq!(graph.nodes_at(BlockPos::new(0, 1, 0)), [0]);
+        assert!(graph.nodes_at(BlockPos::new(1, 1, 0)).is_empty());
+    }
+
+    #[test]
+    fn parses_links() {
+        let graph = Graph::parse(DOT);
+
+        let inputs: Vec<_> = graph.inputs(1).collect();
+        assert_eq!(
+            inputs,
+            [
+                &Link {
+                    from: 0,
+                    to: 1,
+                    side: false,
+                    distance: 2
+                },
+                &Link {
+                    from: 2,
+                    to: 1,
+                    side: true,
+                    distance: 0
+                }
+            ]
+        );
+        assert_eq!(graph.output_count(0), 1);
+        assert_eq!(graph.output_count(1), 0);
+    }
+
+    #[test]
+    fn describes_inputs() {
+        let graph = Graph::parse(DOT);
+
+        let text = describe(&graph, 1, BlockPos::new(100, 64, 10), Some(15));
+
+        assert_eq!(
+            text,
+            "Node 1: Repeater { delay: 2, facing_diode: false }, power 15, 0 outputs\n  \
+             Input from node 0 Torch at 100, 65, 10, distance 2\n  \
+             Side input from node 2 Constant, distance 0"
+        );
+        assert!(
+            describe(&graph, 0, BlockPos::new(0, 0, 0), None)
+                .starts_with("Node 0: Torch, power unknown, 15 when compiled,")
+        );
+    }
+
+    #[test]
+    fn explains_blocks_without_nodes() {
+        let side = RedstoneWireSide::None;
+        let wire = Block::RedstoneWire {
+            wire: RedstoneWire::new(side, side, side, side, 3),
+        };
+        let torch = Block::RedstoneTorch { lit: true };
+
+        assert_eq!(
+            without_nodes(wire, false),
+            "is wire, which redpiler compiles into the links it carries"
+        );
+        assert_eq!(without_nodes(wire, true), without_nodes(wire, false));
+        assert_eq!(without_nodes(torch, true), "was optimised away by redpiler");
+        assert_eq!(without_nodes(torch, false), "was not compiled into a node");
+        assert_eq!(
+            without_nodes(Block::IronBlock {}, true),
+            "is not a redstone component"
+        );
+    }
+
+    #[test]
+    fn reads_nested_fields() {
+        let label = "Outer { empty: 1, ty: Inner { a: (1, 2) }, b: 3 }";
+
+        assert_eq!(field(label, "ty"), Some("Inner { a: (1, 2) }"));
+        assert_eq!(field(label, "b"), Some("3"));
+        assert_eq!(field(label, "c"), None);
+    }
+}
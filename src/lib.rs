@@ -1,29 +1,34 @@
 // TODO: Cleanup
 
-mod fixed_world;
+mod analyzer;
+mod breakpoint;
+mod circuit;
+pub mod compile;
+mod config;
+mod export;
+pub mod fixed_world;
+mod inspect;
+mod permissions;
+mod persist;
+mod probe;
 mod pumpkin_plot;
+mod scheduler;
+pub mod schematic;
+pub mod simulate;
+mod verify;
 
 use std::{
-    sync::{Arc, atomic::Ordering},
-    time::Duration,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::JoinHandle,
 };
 
 use async_trait::async_trait;
-use mchprs_blocks::blocks::{
-    Lever, RedstoneComparator, RedstoneRepeater, RedstoneWire, RedstoneWireSide,
-};
-use mchprs_redpiler::{BackendVariant, Compiler, CompilerOptions};
-use mchprs_world::World;
 use pumpkin_api_macros::{plugin_impl, plugin_method, with_runtime};
 use pumpkin_data::{
-    Block,
-    block_properties::{
-        self, BarrelLikeProperties, BlockProperties, ComparatorLikeProperties, EastWireConnection,
-        EnumVariants, HorizontalFacing, LeverLikeProperties, NorthWireConnection,
-        OakTrapdoorLikeProperties, RedstoneOreLikeProperties, RedstoneWireLikeProperties,
-        RepeaterLikeProperties, SouthWireConnection, StonePressurePlateLikeProperties,
-        WestWireConnection,
-    },
+    BlockDirection,
+    block_properties::{self, RedstoneOreLikeProperties, RedstoneWireLikeProperties},
 };
 
 use pumpkin::{
@@ -32,6 +37,8 @@ use pumpkin::{
         args::{
             Arg, ConsumedArgs,
             bounded_num::{BoundedNumArgumentConsumer, Number},
+            message::MsgArgConsumer,
+            simple::SimpleArgConsumer,
         },
         dispatcher::CommandError,
         tree::{
@@ -40,20 +47,27 @@ use pumpkin::{
         },
     },
     plugin::{
-        Context, Event, EventHandler, EventPriority,
+        Cancellable, Context, EventHandler, EventPriority,
         block::{block_break::BlockBreakEvent, block_place::BlockPlaceEvent},
         player::player_interact_event::PlayerInteractEvent,
     },
     server::Server,
+    world::World,
 };
-use pumpkin_util::{
-    math::position::BlockPos,
-    permission::{Permission, PermissionDefault},
-    text::TextComponent,
+use pumpkin_util::{math::position::BlockPos, text::TextComponent};
+use tokio::{sync::RwLock, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    analyzer::Recording,
+    breakpoint::Condition,
+    circuit::{Events, Input, PlotData},
+    config::Config,
+    persist::{CircuitInfo, SavedSelection, SavedState, array_to_pos, pos_to_array},
+    probe::Probe,
+    scheduler::{MIN_SLEEP, Scheduler, TickRate},
+    schematic::Schematic,
 };
-use tokio::sync::RwLock;
-
-use crate::{fixed_world::TestWorld, pumpkin_plot::PumpkinWorld};
 
 pub type RedstoneWireProperties = RedstoneWireLikeProperties;
 pub type RWallTorchProps = block_properties::FurnaceLikeProperties;
@@ -71,21 +85,37 @@ async fn on_load_internal(plugin: &mut MyPlugin, server: Arc<Context>) -> Result
 
     log::info!("Hello, Pumpkin!");
 
-    let permission_node = "redpiler:compile";
-    let permission = Permission::new(permission_node, "<DESCRIPTION>", PermissionDefault::Allow);
+    {
+        let manager = server.permission_manager.write().await;
+        let mut registry = manager.registry.write().await;
+        permissions::register_permissions(&mut registry)?;
+    }
 
-    let manager = server.permission_manager.write().await;
-    let mut registry = manager.registry.write().await;
-    registry.register_permission(permission)?;
+    {
+        let data_folder = PathBuf::from(server.get_data_folder());
+        let config = Config::load(&data_folder)?;
+
+        let mut data = plugin.data.write().await;
+        data.config = Arc::new(config);
+        data.data_folder = data_folder;
+        data.enabled = true;
+    }
 
     let command_rp = CommandTree::new(
         ["redpiler", "rp"],
         "Compile redstone in selected area for faster execution",
     )
-    .then(literal("compile").execute(Exe {
-        cmd: Command::Compile,
-        data: plugin.data.clone(),
-    }))
+    .then(
+        literal("compile")
+            .execute(Exe {
+                cmd: Command::Compile,
+                data: plugin.data.clone(),
+            })
+            .then(argument("flags", MsgArgConsumer).execute(Exe {
+                cmd: Command::Compile,
+                data: plugin.data.clone(),
+            })),
+    )
     .then(literal("pos1").execute(Exe {
         cmd: Command::Pos1,
         data: plugin.data.clone(),
@@ -97,25 +127,251 @@ async fn on_load_internal(plugin: &mut MyPlugin, server: Arc<Context>) -> Result
     .then(literal("deselect").execute(Exe {
         cmd: Command::Deselect,
         data: plugin.data.clone(),
+    }))
+    .then(literal("reset").execute(Exe {
+        cmd: Command::Reset,
+        data: plugin.data.clone(),
+    }))
+    .then(
+        literal("pause")
+            .execute(Exe {
+                cmd: Command::Pause,
+                data: plugin.data.clone(),
+            })
+            .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                cmd: Command::Pause,
+                data: plugin.data.clone(),
+            })),
+    )
+    .then(
+        literal("resume")
+            .execute(Exe {
+                cmd: Command::Resume,
+                data: plugin.data.clone(),
+            })
+            .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                cmd: Command::Resume,
+                data: plugin.data.clone(),
+            })),
+    )
+    .then(
+        literal("step")
+            .execute(Exe {
+                cmd: Command::Step,
+                data: plugin.data.clone(),
+            })
+            .then(
+                argument(
+                    "ticks",
+                    BoundedNumArgumentConsumer::new()
+                        .min(1)
+                        .max(circuit::MAX_STEP_TICKS),
+                )
+                .execute(Exe {
+                    cmd: Command::Step,
+                    data: plugin.data.clone(),
+                })
+                .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                    cmd: Command::Step,
+                    data: plugin.data.clone(),
+                })),
+            ),
+    )
+    .then(
+        literal("probe")
+            .then(
+                literal("add").then(argument("name", SimpleArgConsumer).execute(Exe {
+                    cmd: Command::ProbeAdd,
+                    data: plugin.data.clone(),
+                })),
+            )
+            .then(literal("list").execute(Exe {
+                cmd: Command::ProbeList,
+                data: plugin.data.clone(),
+            }))
+            .then(
+                literal("remove").then(argument("name", SimpleArgConsumer).execute(Exe {
+                    cmd: Command::ProbeRemove,
+                    data: plugin.data.clone(),
+                })),
+            ),
+    )
+    .then(
+        literal("record")
+            .then(
+                literal("stop")
+                    .execute(Exe {
+                        cmd: Command::RecordStop,
+                        data: plugin.data.clone(),
+                    })
+                    .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                        cmd: Command::RecordStop,
+                        data: plugin.data.clone(),
+                    })),
+            )
+            .then(
+                argument(
+                    "ticks",
+                    BoundedNumArgumentConsumer::new()
+                        .min(1)
+                        .max(analyzer::MAX_TICKS),
+                )
+                .execute(Exe {
+                    cmd: Command::Record,
+                    data: plugin.data.clone(),
+                })
+                .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                    cmd: Command::Record,
+                    data: plugin.data.clone(),
+                })),
+            ),
+    )
+    .then(
+        literal("break")
+            .then(literal("list").execute(Exe {
+                cmd: Command::BreakList,
+                data: plugin.data.clone(),
+            }))
+            .then(
+                literal("clear")
+                    .execute(Exe {
+                        cmd: Command::BreakClear,
+                        data: plugin.data.clone(),
+                    })
+                    .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                        cmd: Command::BreakClear,
+                        data: plugin.data.clone(),
+                    })),
+            )
+            .then(argument("probe", SimpleArgConsumer).then(
+                argument("condition", MsgArgConsumer).execute(Exe {
+                    cmd: Command::Break,
+                    data: plugin.data.clone(),
+                }),
+            )),
+    )
+    .then(literal("inspect").execute(Exe {
+        cmd: Command::Inspect,
+        data: plugin.data.clone(),
+    }))
+    .then(
+        literal("export").then(argument("name", SimpleArgConsumer).execute(Exe {
+            cmd: Command::Export,
+            data: plugin.data.clone(),
+        })),
+    )
+    .then(
+        literal("import").then(
+            argument("file", SimpleArgConsumer)
+                .execute(Exe {
+                    cmd: Command::Import,
+                    data: plugin.data.clone(),
+                })
+                .then(literal("compile").execute(Exe {
+                    cmd: Command::ImportAndCompile,
+                    data: plugin.data.clone(),
+                })),
+        ),
+    )
+    .then(
+        literal("simulate").then(
+            argument("file", SimpleArgConsumer).then(
+                argument(
+                    "ticks",
+                    BoundedNumArgumentConsumer::new()
+                        .min(0)
+                        .max(simulate::MAX_TICKS),
+                )
+                .execute(Exe {
+                    cmd: Command::Simulate,
+                    data: plugin.data.clone(),
+                })
+                .then(argument("inputs", MsgArgConsumer).execute(Exe {
+                    cmd: Command::Simulate,
+                    data: plugin.data.clone(),
+                })),
+            ),
+        ),
+    )
+    .then(
+        literal("verify")
+            .execute(Exe {
+                cmd: Command::Verify,
+                data: plugin.data.clone(),
+            })
+            .then(
+                argument(
+                    "ticks",
+                    BoundedNumArgumentConsumer::new()
+                        .min(1)
+                        .max(verify::MAX_TICKS),
+                )
+                .execute(Exe {
+                    cmd: Command::Verify,
+                    data: plugin.data.clone(),
+                })
+                .then(argument("inputs", MsgArgConsumer).execute(Exe {
+                    cmd: Command::Verify,
+                    data: plugin.data.clone(),
+                })),
+            ),
+    )
+    .then(literal("reload").execute(Exe {
+        cmd: Command::Reload,
+        data: plugin.data.clone(),
     }));
 
     let command_rtps = CommandTree::new(
         ["rtps"],
         "The tick rate for the circuits compiled with redpiler in redstone tick per second",
     )
+    .execute(Exe {
+        cmd: Command::ShowRTPS,
+        data: plugin.data.clone(),
+    })
     .then(
         argument(
             "ticks per second",
-            BoundedNumArgumentConsumer::new().min(10).max(10_000_000),
+            BoundedNumArgumentConsumer::new().min(0.0),
         )
         .execute(Exe {
             cmd: Command::RTPS,
             data: plugin.data.clone(),
-        }),
+        })
+        .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+            cmd: Command::RTPS,
+            data: plugin.data.clone(),
+        })),
+    )
+    .then(
+        literal("sync")
+            .execute(Exe {
+                cmd: Command::SyncRTPS,
+                data: plugin.data.clone(),
+            })
+            .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                cmd: Command::SyncRTPS,
+                data: plugin.data.clone(),
+            })),
+    )
+    .then(
+        literal("unlimited")
+            .execute(Exe {
+                cmd: Command::UnlimitedRTPS,
+                data: plugin.data.clone(),
+            })
+            .then(argument("circuit", SimpleArgConsumer).execute(Exe {
+                cmd: Command::UnlimitedRTPS,
+                data: plugin.data.clone(),
+            })),
     );
 
-    server.register_command(command_rp, permission_node).await;
-    server.register_command(command_rtps, permission_node).await;
+    server
+        .register_command(command_rp, permissions::COMMAND)
+        .await;
+    server
+        .register_command(command_rtps, permissions::RTPS)
+        .await;
 
     log::info!("registered redpiler commands");
 
@@ -149,38 +405,271 @@ async fn on_load_internal(plugin: &mut MyPlugin, server: Arc<Context>) -> Result
 
     log::info!("registered redpiler events");
 
+    restore_saved_state(&plugin.data, &server).await;
+
     let thread_server = server.clone();
     let data = plugin.data.clone();
-    std::thread::spawn(move || {
+    let scheduler = plugin.data.read().await.scheduler.clone();
+    let handle = std::thread::spawn(move || {
         let runtime = tokio::runtime::Builder::new_current_thread()
             .enable_time()
             .build()
             .unwrap();
-        runtime.block_on(tick_loop(data, thread_server));
+        runtime.block_on(tick_loop(data, thread_server, scheduler));
     });
+    plugin.tick_thread = Some(handle);
 
     Ok(())
 }
 
-async fn tick_loop(data: Arc<RwLock<PluginData>>, context: Arc<Context>) {
-    loop {
-        let next_update = tokio::time::Instant::now() + Duration::from_millis(100);
+#[plugin_method]
+async fn on_unload(&mut self, server: Arc<Context>) -> Result<(), String> {
+    on_unload_internal(self, server).await
+}
+
+#[inline(always)]
+async fn on_unload_internal(plugin: &mut MyPlugin, server: Arc<Context>) -> Result<(), String> {
+    if let Some(tick_thread) = plugin.tick_thread.take() {
+        plugin.data.read().await.scheduler.stop();
+        // Joining blocks until the current update is done, which must not stall the runtime
+        let joined = tokio::task::spawn_blocking(move || tick_thread.join()).await;
+        if !matches!(joined, Ok(Ok(()))) {
+            log::error!("redpiler tick loop panicked");
+        }
+    }
 
-        {
-            let mut data = data.write().await;
-            let ticks = data.rtps / 10;
+    server.unregister_command("redpiler").await;
+    server.unregister_command("rtps").await;
+
+    {
+        let manager = server.permission_manager.write().await;
+        let mut registry = manager.registry.write().await;
+        permissions::unregister_permissions(&mut registry);
+    }
+
+    let mut data = plugin.data.write().await;
+
+    // Pumpkin can't unregister event handlers, so they are disabled instead
+    data.enabled = false;
+
+    // Save before resetting so the circuits are compiled again when the plugin is loaded
+    data.save();
+    data.pending.clear();
+    for plot in data.plots.drain(..) {
+        plot.reset().await;
+    }
+
+    log::info!("unloaded redpiler");
 
-            let Some(plot_data) = &mut data.plot else {
+    Ok(())
+}
+
+/// Restores the selections and recompiles the circuits that were saved by [`PluginData::save`].
+async fn restore_saved_state(data: &Arc<RwLock<PluginData>>, context: &Arc<Context>) {
+    let mut data = data.write().await;
+
+    let state = match SavedState::load(&data.data_folder) {
+        Ok(state) => state,
+        Err(err) => {
+            log::error!("{err}");
+            return;
+        }
+    };
+
+    for selection in state.selections {
+        data.selections.insert(
+            selection.player,
+            Selection {
+                pos1: selection.pos1.map(array_to_pos),
+                pos2: selection.pos2.map(array_to_pos),
+            },
+        );
+    }
+
+    let worlds = context.server.worlds.read().await.clone();
+    for mut info in state.circuits {
+        let rate = data.config.clamp_rate(info.rate);
+        if rate != info.rate {
+            log::warn!(
+                "Circuit {} was saved with {}, running it at {rate} instead",
+                info.name,
+                info.rate
+            );
+            info.rate = rate;
+        }
+        // Circuits saved before one shot flags were left out would export again on every start
+        info.flags = config::persistent_flags(&info.flags);
+
+        let Some(world) = worlds.iter().find(|w| w.get_world_name() == info.world) else {
+            log::warn!(
+                "Not recompiling circuit {} because world {} does not exist, keeping it",
+                info.name,
+                info.world
+            );
+            data.pending.push(info);
+            continue;
+        };
+
+        let name = info.name.clone();
+        match PlotData::compile(world.clone(), info.clone()).await {
+            Ok(mut plot) => {
+                log::info!("Recompiled circuit {name}");
+                match plot.export_dot(&data.data_folder) {
+                    Some(Ok(export)) => log::info!("Exported graph to {}", export.path.display()),
+                    Some(Err(err)) => log::error!("{err}"),
+                    None => {}
+                }
+                data.plots.push(Arc::new(plot));
+                data.scheduler.wake();
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to recompile circuit {name}, keeping it for the next start: {err}"
+                );
+                data.pending.push(info);
+            }
+        }
+    }
+}
+
+async fn tick_loop(
+    data: Arc<RwLock<PluginData>>,
+    context: Arc<Context>,
+    scheduler: Arc<Scheduler>,
+) {
+    while scheduler.is_running() {
+        // Only hold the plugin lock long enough to see which circuits there are, each circuit has
+        // its own lock for the simulation
+        let (plots, config, data_folder) = {
+            let data = data.read().await;
+            (
+                data.plots.clone(),
+                data.config.clone(),
+                data.data_folder.clone(),
+            )
+        };
+
+        let now = Instant::now();
+        let mut lowered_rtps = false;
+        let mut next_wake: Option<Instant> = None;
+        let mut failed = Vec::new();
+        for plot in &plots {
+            // Each update runs as its own task, so a panic in the compiler only stops that circuit
+            let update = {
+                let plot = plot.clone();
+                let config = config.clone();
+                tokio::spawn(async move { plot.update(now, &config).await }).await
+            };
+            let Ok(update) = update else {
+                log::error!("Circuit {} panicked, stopping it", plot.info.name);
+                plot.stop();
+                failed.push(plot.clone());
                 continue;
             };
+            lowered_rtps |= update.lowered_rtps;
+
+            if let Some(warning) = update.lag_warning {
+                let owner = context.server.get_player_by_uuid(plot.info.owner).await;
+                if let Some(player) = owner {
+                    player
+                        .send_system_message(&TextComponent::text(warning))
+                        .await;
+                }
+            }
+
+            report_events(
+                &context.server,
+                &data_folder,
+                &plot.info.name,
+                update.events,
+            )
+            .await;
+
+            if let Some(wake) = update.next_wake {
+                next_wake = Some(next_wake.map_or(wake, |next| next.min(wake)));
+            }
+        }
+
+        if !failed.is_empty() {
+            let mut data = data.write().await;
+            data.plots
+                .retain(|plot| !failed.iter().any(|failed| Arc::ptr_eq(plot, failed)));
+            data.save();
+        } else if lowered_rtps {
+            data.read().await.save();
+        }
+
+        for plot in failed {
+            if let Some(player) = context.server.get_player_by_uuid(plot.info.owner).await {
+                player
+                    .send_system_message(&TextComponent::text(format!(
+                        "Circuit {} stopped after an error in the compiler, compile it again",
+                        plot.info.name
+                    )))
+                    .await;
+            }
+        }
+
+        match next_wake {
+            // Nothing is compiled, so there is nothing to do until a circuit gets compiled
+            None => scheduler.park().await,
+            Some(deadline) => {
+                let deadline = deadline.max(Instant::now() + MIN_SLEEP);
+                scheduler.sleep_until(deadline).await;
+            }
+        }
+    }
+}
+
+/// Tells the players that added the probes and breakpoints what happened to them, and writes
+/// finished recordings to `data_folder`.
+async fn report_events(server: &Server, data_folder: &Path, circuit: &str, events: Events) {
+    for change in events.probe_changes {
+        let Some(player) = server.get_player_by_uuid(change.owner).await else {
+            continue;
+        };
+        player
+            .send_system_message(&TextComponent::text(format!(
+                "Probe {} of circuit {circuit}: {} -> {}",
+                change.name, change.from, change.to
+            )))
+            .await;
+    }
+
+    for hit in events.breakpoint_hits {
+        let Some(player) = server.get_player_by_uuid(hit.owner).await else {
+            continue;
+        };
+        player
+            .send_system_message(&TextComponent::text(format!(
+                "Circuit {circuit} paused on tick {}: probe {} {} with power {}",
+                hit.tick, hit.probe, hit.condition, hit.power
+            )))
+            .await;
+    }
 
-            plot_data.compiler.tickn(ticks);
-            let mut world = PumpkinWorld::new(plot_data.base);
-            plot_data.compiler.flush(&mut world);
-            world.apply(plot_data.world.clone()).await;
+    for recording in events.recordings {
+        let message = recording_message(data_folder, circuit, &recording);
+        if let Some(player) = server.get_player_by_uuid(recording.owner).await {
+            player
+                .send_system_message(&TextComponent::text(message))
+                .await;
         }
+    }
+}
 
-        tokio::time::sleep_until(next_update).await;
+/// Writes the recording to a VCD file and describes where it went.
+fn recording_message(data_folder: &Path, circuit: &str, recording: &Recording) -> String {
+    match analyzer::write_vcd(data_folder, circuit, recording) {
+        Ok(path) => format!(
+            "Recorded {} ticks of circuit {circuit} to {}",
+            recording.recorded,
+            path.display()
+        ),
+        Err(err) => {
+            log::error!("{err}");
+            format!("Failed to save the recording of circuit {circuit}")
+        }
     }
 }
 
@@ -193,25 +682,34 @@ struct BreakHandler {
 impl EventHandler<BlockBreakEvent> for BreakHandler {
     async fn handle_blocking(&self, _server: &Arc<Server>, event: &mut BlockBreakEvent) {
         let pos = event.block_position;
+        // Without a player there is no telling which world the block was broken in
+        let Some(player) = &event.player else {
+            return;
+        };
+        let world = player.world();
 
         let mut data = self.data.write().await;
 
-        let Some(plot_data) = &data.plot else {
+        if !data.enabled {
             return;
-        };
+        }
 
-        let mchprs_pos = mchprs_blocks::BlockPos::new(
-            pos.0.x - plot_data.base.x,
-            pos.0.y - plot_data.base.y,
-            pos.0.z - plot_data.base.z,
-        );
+        let Some(index) = data.plot_index_at(&world, pos) else {
+            return;
+        };
 
-        if !plot_data.plot.block_in_world(mchprs_pos) {
+        // A kept circuit has to write the block again when it next changes
+        if !data.config.auto_invalidate {
+            let plot_data = data.plots[index].clone();
+            drop(data);
+            plot_data.forget_applied(pos).await;
             return;
         }
 
-        data.plot = None;
-        log::info!("Invalidated plot");
+        let plot_data = data.plots.remove(index);
+        plot_data.stop();
+        data.save();
+        log::info!("Invalidated plot {}", plot_data.info.name);
     }
 }
 
@@ -223,12 +721,57 @@ struct PlaceHandler {
 #[async_trait]
 impl EventHandler<BlockPlaceEvent> for PlaceHandler {
     async fn handle_blocking(&self, _server: &Arc<Server>, event: &mut BlockPlaceEvent) {
-        // TODO: only invalidate plot when change happens inside of it
-        let mut data = self.data.write().await;
+        let auto_invalidate = {
+            let data = self.data.read().await;
+            if !data.enabled || data.plots.is_empty() {
+                return;
+            }
+            data.config.auto_invalidate
+        };
+
+        // The event doesn't say where the block goes, only that it is placed against the block
+        // the player is looking at, so every circuit next to that block is invalidated. Kept
+        // circuits forget the states they wrote around it instead
+        let world = event.player.world();
+        let Some(against) = probe::targeted_block(&world, &event.player).await else {
+            return;
+        };
 
-        data.plot = None;
+        if !auto_invalidate {
+            let plots: Vec<_> = {
+                let data = self.data.read().await;
+                data.plots
+                    .iter()
+                    .filter(|plot| plot.touches(&world, against))
+                    .cloned()
+                    .collect()
+            };
+            for plot in plots {
+                plot.forget_applied(against).await;
+                for direction in BlockDirection::all() {
+                    plot.forget_applied(against.offset(direction.to_offset()))
+                        .await;
+                }
+            }
+            return;
+        }
 
-        log::info!("Invalidated plot");
+        let mut data = self.data.write().await;
+        let mut invalidated = Vec::new();
+        data.plots.retain(|plot| {
+            if !plot.touches(&world, against) {
+                return true;
+            }
+            plot.stop();
+            invalidated.push(plot.info.name.clone());
+            false
+        });
+        if invalidated.is_empty() {
+            return;
+        }
+
+        data.save();
+        log::info!("Invalidated plots {}", invalidated.join(", "));
     }
 }
 
@@ -246,54 +789,76 @@ impl EventHandler<PlayerInteractEvent> for InputHandler {
 
         let mut data = self.data.write().await;
 
-        let Some(plot_data) = &mut data.plot else {
+        if !data.enabled {
             return;
-        };
-
-        let mchprs_pos = mchprs_blocks::BlockPos::new(
-            pos.0.x - plot_data.base.x,
-            pos.0.y - plot_data.base.y,
-            pos.0.z - plot_data.base.z,
-        );
+        }
 
-        if !plot_data.plot.block_in_world(mchprs_pos) {
-            log::info!("outside interact with block at {:?}", mchprs_pos);
+        let holds_wand = {
+            let item = event.item.lock().await;
+            data.config.is_wand(item.item.registry_key)
+        };
+        if holds_wand && event.player.has_permission(permissions::SELECTION).await {
+            let selection = data
+                .selections
+                .entry(event.player.gameprofile.id)
+                .or_default();
+            if event.action.is_left_click() {
+                selection.pos1 = Some(pos);
+            } else {
+                selection.pos2 = Some(pos);
+            }
+            data.save();
+            event.set_cancelled(true);
             return;
         }
 
+        let world = event.player.world();
+        let Some(index) = data.plot_index_at(&world, pos) else {
+            return;
+        };
+
         if event.action.is_right_click() {
-            data.plot = None;
-            log::info!("Invalidated plot");
+            if !data.config.auto_invalidate {
+                // Right clicking can change the block, like the delay of a repeater
+                let plot_data = data.plots[index].clone();
+                drop(data);
+                plot_data.forget_applied(pos).await;
+                return;
+            }
+            let plot_data = data.plots.remove(index);
+            plot_data.stop();
+            data.save();
+            log::info!("Invalidated plot {}", plot_data.info.name);
             return;
         }
 
+        let plot_data = &data.plots[index];
+        let mchprs_pos = plot_data.to_local(pos);
+
         log::info!("interact with block at {:?}", mchprs_pos);
 
-        if matches!(
-            plot_data.plot.get_block(mchprs_pos),
-            mchprs_blocks::blocks::Block::Air {}
-        ) {
+        // Using any other block would panic in the compiler, as it has no node there
+        if !plot_data.is_input(mchprs_pos) {
             return;
         }
 
-        plot_data.compiler.on_use_block(mchprs_pos);
+        // The tick loop handles the input, so this doesn't wait for a running tick
+        plot_data.send_input(Input::Use(mchprs_pos));
+        data.scheduler.wake();
     }
 }
 
 #[plugin_impl]
 pub struct MyPlugin {
     data: Arc<RwLock<PluginData>>,
+    tick_thread: Option<JoinHandle<()>>,
 }
 
 impl MyPlugin {
     pub fn new() -> Self {
-        println!("hello from redpiler plugin");
-
         MyPlugin {
-            data: Arc::new(RwLock::new(PluginData {
-                rtps: 10,
-                ..Default::default()
-            })),
+            data: Arc::new(RwLock::new(PluginData::default())),
+            tick_thread: None,
         }
     }
 }
@@ -304,19 +869,83 @@ impl Default for MyPlugin {
     }
 }
 
-#[derive(Default)]
-struct PluginData {
+#[derive(Default, Clone, Copy)]
+struct Selection {
     pos1: Option<BlockPos>,
     pos2: Option<BlockPos>,
-    plot: Option<PlotData>,
-    rtps: u64,
 }
 
-struct PlotData {
-    world: Arc<pumpkin::world::World>,
-    base: mchprs_blocks::BlockPos,
-    plot: TestWorld,
-    compiler: Compiler,
+#[derive(Default)]
+struct PluginData {
+    selections: HashMap<Uuid, Selection>,
+    plots: Vec<Arc<PlotData>>,
+    /// Saved circuits that could not be compiled again, kept so saving doesn't lose them
+    pending: Vec<CircuitInfo>,
+    config: Arc<Config>,
+    data_folder: PathBuf,
+    /// Whether the plugin is loaded, the event handlers do nothing once this is cleared
+    enabled: bool,
+    scheduler: Arc<Scheduler>,
+}
+
+impl PluginData {
+    fn plot_index_at(&self, world: &World, pos: BlockPos) -> Option<usize> {
+        self.plots.iter().position(|plot| plot.contains(world, pos))
+    }
+
+    /// The circuits a command of `player` applies to: the ones named `name` preferring their own,
+    /// otherwise the one they are standing in, otherwise all of their own circuits.
+    fn target_plots(
+        &self,
+        player: Uuid,
+        world: &World,
+        pos: BlockPos,
+        name: Option<&str>,
+    ) -> Vec<usize> {
+        let indices = 0..self.plots.len();
+        let owned = |index: &usize| self.plots[*index].info.owner == player;
+
+        if let Some(name) = name {
+            let named: Vec<usize> = indices
+                .filter(|&index| self.plots[index].info.name == name)
+                .collect();
+            if named.iter().any(owned) {
+                return named.into_iter().filter(owned).collect();
+            }
+            return named;
+        }
+
+        match self.plot_index_at(world, pos) {
+            Some(index) => vec![index],
+            None => indices.filter(owned).collect(),
+        }
+    }
+
+    /// Writes the selections and all circuits that did not opt out of persisting to disk.
+    fn save(&self) {
+        let state = SavedState {
+            selections: self
+                .selections
+                .iter()
+                .map(|(player, selection)| SavedSelection {
+                    player: *player,
+                    pos1: selection.pos1.map(pos_to_array),
+                    pos2: selection.pos2.map(pos_to_array),
+                })
+                .collect(),
+            circuits: self
+                .plots
+                .iter()
+                .filter(|plot| plot.persist)
+                .map(|plot| plot.saved_info())
+                .chain(self.pending.iter().cloned())
+                .collect(),
+        };
+
+        if let Err(err) = state.save(&self.data_folder) {
+            log::error!("{err}");
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -326,6 +955,101 @@ enum Command {
     Pos2,
     Deselect,
     RTPS,
+    SyncRTPS,
+    UnlimitedRTPS,
+    ShowRTPS,
+    Reset,
+    Pause,
+    Resume,
+    Step,
+    ProbeAdd,
+    ProbeList,
+    ProbeRemove,
+    Record,
+    RecordStop,
+    Break,
+    BreakList,
+    BreakClear,
+    Inspect,
+    Export,
+    Import,
+    ImportAndCompile,
+    Simulate,
+    Verify,
+    Reload,
+}
+
+impl Command {
+    fn permission(self) -> &'static str {
+        match self {
+            Command::Compile => permissions::COMPILE,
+            Command::Pos1 | Command::Pos2 | Command::Deselect => permissions::SELECTION,
+            Command::RTPS | Command::SyncRTPS | Command::UnlimitedRTPS | Command::ShowRTPS => {
+                permissions::RTPS
+            }
+            Command::Reset => permissions::RESET,
+            Command::Pause | Command::Resume | Command::Step => permissions::STEP,
+            Command::ProbeAdd
+            | Command::ProbeList
+            | Command::ProbeRemove
+            | Command::Record
+            | Command::RecordStop
+            | Command::Break
+            | Command::BreakList
+            | Command::BreakClear => permissions::PROBE,
+            Command::Inspect => permissions::INSPECT,
+            Command::Export | Command::Import | Command::ImportAndCompile | Command::Simulate => {
+                permissions::SCHEMATIC
+            }
+            Command::Verify => permissions::VERIFY,
+            Command::Reload => permissions::ADMIN,
+        }
+    }
+}
+
+/// Fails when none of the circuits are targeted, and requires the admin permission when any of them
+/// belongs to someone else.
+async fn require_targets(
+    sender: &CommandSender,
+    plots: &[Arc<PlotData>],
+    targets: &[usize],
+    player: Uuid,
+) -> Result<(), CommandError> {
+    if targets.is_empty() {
+        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+            "There is no compiled circuit here or owned by you",
+        ))));
+    }
+
+    if targets
+        .iter()
+        .any(|&index| plots[index].info.owner != player)
+    {
+        permissions::require(sender, permissions::ADMIN).await?;
+    }
+
+    Ok(())
+}
+
+/// The number of blocks between `p1` and `p2`, inclusive.
+fn selection_volume(p1: BlockPos, p2: BlockPos) -> u64 {
+    let size = |a: i32, b: i32| u64::from(a.abs_diff(b)) + 1;
+    size(p1.0.x, p2.0.x) * size(p1.0.y, p2.0.y) * size(p1.0.z, p2.0.z)
+}
+
+/// Fails when there are more than `max_volume` blocks between `p1` and `p2`.
+fn require_selection_volume(
+    p1: BlockPos,
+    p2: BlockPos,
+    max_volume: u64,
+) -> Result<(), CommandError> {
+    let volume = selection_volume(p1, p2);
+    if volume > max_volume {
+        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+            format!("Selection of {volume} blocks is larger than the limit of {max_volume} blocks"),
+        ))));
+    }
+    Ok(())
 }
 
 struct Exe {
@@ -333,25 +1057,120 @@ struct Exe {
     cmd: Command,
 }
 
-const AIR: u16 = Block::AIR.id;
-const LEVER: u16 = Block::LEVER.id;
-const STONE_BUTTON: u16 = Block::STONE_BUTTON.id;
-const STONE_PRESSURE_PLATE: u16 = Block::STONE_PRESSURE_PLATE.id;
-const REDSTONE_BLOCK: u16 = Block::REDSTONE_BLOCK.id;
+impl Exe {
+    /// Compiles the selection of the player, replacing the circuits it overlaps.
+    async fn compile_selection(
+        &self,
+        sender: &mut CommandSender,
+        world: &Arc<pumpkin::world::World>,
+        player_id: Uuid,
+        player_name: &str,
+        user_flags: &str,
+    ) -> Result<(), CommandError> {
+        let mut data = self.data.write().await;
+        let selection = data.selections.get(&player_id).copied().unwrap_or_default();
+        let (Some(p1), Some(p2)) = (selection.pos1, selection.pos2) else {
+            return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                "Select an area with /redpiler pos1 and pos2 first",
+            ))));
+        };
 
-const REDSTONE_LAMP: u16 = Block::REDSTONE_LAMP.id;
-const IRON_TRAPDOOR: u16 = Block::IRON_TRAPDOOR.id;
+        let flags = format!("{} {}", data.config.compile_flags, user_flags);
+        let compile_flags = config::parse_compile_flags(&flags)
+            .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?;
 
-const REDSTONE_WIRE: u16 = Block::REDSTONE_WIRE.id;
-const REDSTONE_TORCH: u16 = Block::REDSTONE_TORCH.id;
-const REDSTONE_WALL_TORCH: u16 = Block::REDSTONE_WALL_TORCH.id;
+        let x1 = p1.0.x.min(p2.0.x);
+        let x2 = p1.0.x.max(p2.0.x);
+        let y1 = p1.0.y.min(p2.0.y);
+        let y2 = p1.0.y.max(p2.0.y);
+        let z1 = p1.0.z.min(p2.0.z);
+        let z2 = p1.0.z.max(p2.0.z);
 
-const REPEATER: u16 = Block::REPEATER.id;
+        require_selection_volume(p1, p2, data.config.max_selection_volume)?;
 
-const COMPARATOR: u16 = Block::COMPARATOR.id;
-const BARREL: u16 = Block::BARREL.id;
+        let name = compile_flags
+            .name
+            .unwrap_or_else(|| player_name.to_string());
 
-const TARGET: u16 = Block::TARGET.id;
+        // Circuits that are compiled again replace the old one, as do overlapping circuits
+        let replaced = |plot: &PlotData| {
+            plot.overlaps(world, p1, p2) || (plot.info.owner == player_id && plot.info.name == name)
+        };
+        if data
+            .plots
+            .iter()
+            .any(|plot| replaced(plot) && plot.info.owner != player_id)
+        {
+            permissions::require(sender, permissions::ADMIN).await?;
+        }
+
+        let mut rate = TickRate::Rtps(data.config.default_rtps);
+        let mut index = 0;
+        while index < data.plots.len() {
+            if !replaced(&data.plots[index]) {
+                index += 1;
+                continue;
+            }
+            let plot = data.plots.remove(index);
+            if plot.info.name == name {
+                rate = *plot.rate();
+            }
+            plot.reset().await;
+        }
+
+        sender
+            .send_message(TextComponent::text(format!(
+                "Compiling selection {}, {}, {} ; {}, {}, {}",
+                x1, y1, z1, x2, y2, z2
+            )))
+            .await;
+
+        let info = CircuitInfo {
+            name,
+            owner: player_id,
+            world: world.get_world_name().to_string(),
+            pos1: pos_to_array(p1),
+            pos2: pos_to_array(p2),
+            flags,
+            rate,
+        };
+        let mut plot = PlotData::compile(world.clone(), info)
+            .await
+            .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?;
+
+        let export = plot.export_dot(&data.data_folder);
+
+        data.plots.push(Arc::new(plot));
+        data.save();
+        data.scheduler.wake();
+
+        sender
+            .send_message(TextComponent::text(format!("Compiled successfully")))
+            .await;
+
+        match export {
+            Some(Ok(export)) => {
+                sender
+                    .send_message(TextComponent::text(format!(
+                        "Exported graph with {} nodes and {} links to {}",
+                        export.nodes,
+                        export.links,
+                        export.path.display()
+                    )))
+                    .await;
+            }
+            Some(Err(err)) => {
+                log::error!("{err}");
+                sender
+                    .send_message(TextComponent::text("Failed to export the graph"))
+                    .await;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
 
 #[async_trait]
 impl CommandExecutor for Exe {
@@ -362,371 +1181,737 @@ impl CommandExecutor for Exe {
         args: &ConsumedArgs<'a>,
     ) -> Result<(), CommandError> {
         let world = sender.world().await;
-        log::info!("hello execute {:?}", self.cmd);
-        log::info!("has world {:?}", world.is_some());
-        log::info!(
-            "player {:?}",
-            sender.as_player().map(|p| p.position().to_block_pos())
-        );
 
         let (Some(world), Some(player)) = (world, sender.as_player()) else {
             log::error!("Redpiler commands must be run by a player");
             return Err(CommandError::PermissionDenied);
         };
 
+        permissions::require(sender, self.cmd.permission()).await?;
+
+        let player_id = player.gameprofile.id;
+        let player_pos = player.position().to_block_pos();
+        let circuit_name = match args.get("circuit") {
+            Some(Arg::Simple(name)) => Some(*name),
+            _ => None,
+        };
+
         match self.cmd {
-            Command::RTPS => {
-                let mut data = self.data.write().await;
-                let Some((name, arg)) = args.iter().next() else {
+            Command::ShowRTPS => {
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+                if targets.is_empty() {
                     return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
-                        "Missing argument",
+                        "There is no compiled circuit here or owned by you",
                     ))));
-                };
+                }
 
-                match arg {
-                    Arg::Num(Ok(Number::I64(n))) => {
-                        data.rtps = *n as u64;
-                        sender
-                            .send_message(TextComponent::text("successfully set rtps"))
-                            .await;
-                    }
-                    Arg::Num(Ok(Number::I32(n))) => {
-                        data.rtps = *n as u64;
-                        sender
-                            .send_message(TextComponent::text("successfully set rtps"))
-                            .await;
-                    }
-                    _ => {
+                for index in targets {
+                    let plot = &data.plots[index];
+                    let message = {
+                        let rate = *plot.rate();
+                        let sim = plot.sim();
+                        let mut message = format!("Circuit {} runs at {rate}", plot.info.name);
+                        if let Some(measured_rtps) = sim.measured_rtps() {
+                            message += &format!(", measured {measured_rtps:.1} rtps");
+                        }
+                        if sim.is_paused() {
+                            message += ", paused";
+                        }
+                        message
+                    };
+
+                    sender.send_message(TextComponent::text(message)).await;
+                }
+            }
+            Command::RTPS | Command::SyncRTPS | Command::UnlimitedRTPS => {
+                let data = self.data.read().await;
+
+                let rate = if let Command::SyncRTPS = self.cmd {
+                    TickRate::Sync
+                } else if let Command::UnlimitedRTPS = self.cmd {
+                    permissions::require(sender, permissions::RTPS_HIGH).await?;
+                    TickRate::Unlimited
+                } else {
+                    let Some(arg) = args.get("ticks per second") else {
+                        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                            "Missing argument",
+                        ))));
+                    };
+
+                    let rtps = match arg {
+                        Arg::Num(Ok(Number::F64(n))) => *n,
+                        Arg::Num(Ok(Number::F32(n))) => *n as f64,
+                        Arg::Num(Ok(Number::I64(n))) => *n as f64,
+                        Arg::Num(Ok(Number::I32(n))) => *n as f64,
+                        _ => {
+                            return Err(CommandError::CommandFailed(Box::new(
+                                TextComponent::text("Expected positive number argument"),
+                            )));
+                        }
+                    };
+
+                    if rtps < data.config.min_rtps || rtps > data.config.max_rtps {
                         return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
-                            "Expected positive integer argument",
+                            format!(
+                                "rtps must be between {} and {}",
+                                data.config.min_rtps, data.config.max_rtps
+                            ),
                         ))));
                     }
+
+                    if rtps > data.config.high_rtps_threshold {
+                        permissions::require(sender, permissions::RTPS_HIGH).await?;
+                    }
+
+                    TickRate::Rtps(rtps)
+                };
+
+                let targets = data.target_plots(player_id, &world, player_pos, circuit_name);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                for &index in &targets {
+                    *data.plots[index].rate() = rate;
+                }
+
+                data.save();
+                data.scheduler.wake();
+                for index in targets {
+                    sender
+                        .send_message(TextComponent::text(format!(
+                            "Set tick rate of circuit {} to {rate}",
+                            data.plots[index].info.name
+                        )))
+                        .await;
                 }
             }
             Command::Compile => {
-                // TODO: Add all components including all containers
-                // TODO: Pass along pending ticks
-                // TODO: Parse compile flags
-
-                for (a, b) in args.iter() {
-                    if let Arg::Simple(simple) = b {
-                        log::info!("{:?} {:?}", a, simple);
-                    } else {
-                        log::info!("{:?}", a);
-                    }
+                let user_flags = match args.get("flags") {
+                    Some(Arg::Msg(flags)) => flags.as_str(),
+                    _ => "",
+                };
+                self.compile_selection(
+                    sender,
+                    &world,
+                    player_id,
+                    &player.gameprofile.name,
+                    user_flags,
+                )
+                .await?;
+            }
+            Command::Pos1 => {
+                let mut data = self.data.write().await;
+                data.selections.entry(player_id).or_default().pos1 =
+                    Some(player.position().sub_raw(0.5, 0.5, 0.5).to_block_pos());
+                data.save();
+            }
+            Command::Pos2 => {
+                let mut data = self.data.write().await;
+                data.selections.entry(player_id).or_default().pos2 =
+                    Some(player.position().sub_raw(0.5, 0.5, 0.5).to_block_pos());
+                data.save();
+            }
+            Command::Deselect => {
+                let mut data = self.data.write().await;
+                data.selections.remove(&player_id);
+                data.save();
+            }
+            Command::Reset => {
+                let mut data = self.data.write().await;
+
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                for index in targets.into_iter().rev() {
+                    let plot = data.plots.remove(index);
+                    plot.reset().await;
                 }
+                data.save();
 
-                let mut data = self.data.write().await;
-                let (Some(p1), Some(p2)) = (data.pos1, data.pos2) else {
-                    return Err(CommandError::PermissionDenied);
+                sender
+                    .send_message(TextComponent::text("Reset compiled circuit"))
+                    .await;
+            }
+            Command::Pause | Command::Resume | Command::Step => {
+                // Steps can take a while, so the plugin lock is not held while running them
+                let (plots, data_folder, scheduler) = {
+                    let data = self.data.read().await;
+                    let targets = data.target_plots(player_id, &world, player_pos, circuit_name);
+                    require_targets(sender, &data.plots, &targets, player_id).await?;
+                    let plots: Vec<_> = targets
+                        .into_iter()
+                        .map(|index| data.plots[index].clone())
+                        .collect();
+                    (plots, data.data_folder.clone(), data.scheduler.clone())
                 };
 
-                let x1 = p1.0.x.min(p2.0.x);
-                let x2 = p1.0.x.max(p2.0.x);
-                let y1 = p1.0.y.min(p2.0.y);
-                let y2 = p1.0.y.max(p2.0.y);
-                let z1 = p1.0.z.min(p2.0.z);
-                let z2 = p1.0.z.max(p2.0.z);
+                let ticks = match args.get("ticks") {
+                    Some(Arg::Num(Ok(Number::I32(n)))) => *n as u64,
+                    _ => 1,
+                };
+
+                for plot in plots {
+                    let message = match self.cmd {
+                        Command::Pause => {
+                            plot.pause();
+                            format!("Paused circuit {}", plot.info.name)
+                        }
+                        Command::Resume => {
+                            plot.resume();
+                            format!("Resumed circuit {}", plot.info.name)
+                        }
+                        _ => {
+                            let (stepped, events) = plot.clone().step(ticks).await;
+                            let name = &plot.info.name;
+                            let message = match events.breakpoint_hits.first() {
+                                Some(hit) => format!(
+                                    "Stepped circuit {name} by {stepped} of {ticks} ticks, \
+                                     stopped by the breakpoint on probe {} {}",
+                                    hit.probe, hit.condition
+                                ),
+                                None => format!("Stepped circuit {name} by {stepped} ticks"),
+                            };
+                            report_events(server, &data_folder, name, events).await;
+                            message
+                        }
+                    };
+                    sender.send_message(TextComponent::text(message)).await;
+                }
+
+                scheduler.wake();
+            }
+            Command::ProbeAdd => {
+                let Some(Arg::Simple(name)) = args.get("name") else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing probe name",
+                    ))));
+                };
+                let Some(pos) = probe::targeted_block(&world, &player).await else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        format!(
+                            "You are not looking at a block within {} blocks",
+                            probe::REACH
+                        ),
+                    ))));
+                };
+
+                let data = self.data.read().await;
+                let Some(index) = data.plot_index_at(&world, pos) else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "The block you are looking at is not part of a compiled circuit",
+                    ))));
+                };
+                require_targets(sender, &data.plots, &[index], player_id).await?;
+                let plot = &data.plots[index];
+
+                let (block, _) = world.get_block_and_state(&pos).await;
+                if block.id == pumpkin_data::Block::COMPARATOR.id {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Comparators only show their output strength in the world after a reset, \
+                         probe the block they power instead",
+                    ))));
+                }
+                let Some(power) = probe::world_power(&world, pos).await else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        format!("{} has no power level to probe", block.name),
+                    ))));
+                };
+
+                plot.add_probe(Probe {
+                    name: name.to_string(),
+                    owner: player_id,
+                    pos: plot.to_local(pos),
+                    power,
+                });
 
                 sender
                     .send_message(TextComponent::text(format!(
-                        "Compiling selection {}, {}, {} ; {}, {}, {}",
-                        x1, y1, z1, x2, y2, z2
+                        "Probing {} at {}, {}, {} as {name} in circuit {}, power {power}",
+                        block.name, pos.0.x, pos.0.y, pos.0.z, plot.info.name
                     )))
                     .await;
+            }
+            Command::ProbeList => {
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+
+                let mut message = String::new();
+                for index in targets {
+                    let plot = &data.plots[index];
+                    for probe in plot.probes() {
+                        message += &format!(
+                            "\nProbe {} at {}, {}, {} in circuit {}: {}",
+                            probe.name,
+                            plot.base.x + probe.pos.x,
+                            plot.base.y + probe.pos.y,
+                            plot.base.z + probe.pos.z,
+                            plot.info.name,
+                            probe.power
+                        );
+                    }
+                }
 
-                let mut plot = fixed_world::TestWorld::new(x2 - x1, y2 - y1, z2 - z1);
-
-                for z in z1..=z2 {
-                    for y in y1..=y2 {
-                        for x in x1..=x2 {
-                            let pos = BlockPos::new(x, y, z);
-                            let mchprs_pos = mchprs_blocks::BlockPos::new(x - x1, y - y1, z - z1);
-
-                            let (b, s) = world.get_block_and_state(&pos).await;
-
-                            let mchprs_block = match b.id {
-                                AIR => continue,
-                                REDSTONE_WIRE => {
-                                    let props = RedstoneWireProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::RedstoneWire {
-                                        wire: RedstoneWire::new(
-                                            match props.north {
-                                                NorthWireConnection::Up => RedstoneWireSide::Up,
-                                                NorthWireConnection::Side => RedstoneWireSide::Side,
-                                                NorthWireConnection::None => RedstoneWireSide::None,
-                                            },
-                                            match props.south {
-                                                SouthWireConnection::Up => RedstoneWireSide::Up,
-                                                SouthWireConnection::Side => RedstoneWireSide::Side,
-                                                SouthWireConnection::None => RedstoneWireSide::None,
-                                            },
-                                            match props.east {
-                                                EastWireConnection::Up => RedstoneWireSide::Up,
-                                                EastWireConnection::Side => RedstoneWireSide::Side,
-                                                EastWireConnection::None => RedstoneWireSide::None,
-                                            },
-                                            match props.west {
-                                                WestWireConnection::Up => RedstoneWireSide::Up,
-                                                WestWireConnection::Side => RedstoneWireSide::Side,
-                                                WestWireConnection::None => RedstoneWireSide::None,
-                                            },
-                                            props.power.to_index() as u8,
-                                        ),
-                                    }
-                                }
-                                STONE_BUTTON => {
-                                    let props = LeverLikeProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::StoneButton {
-                                        button: mchprs_blocks::blocks::StoneButton::new(
-                                            match props.face {
-                                                block_properties::BlockFace::Floor => {
-                                                    mchprs_blocks::blocks::ButtonFace::Floor
-                                                }
-                                                block_properties::BlockFace::Wall => {
-                                                    mchprs_blocks::blocks::ButtonFace::Wall
-                                                }
-                                                block_properties::BlockFace::Ceiling => {
-                                                    mchprs_blocks::blocks::ButtonFace::Ceiling
-                                                }
-                                            },
-                                            direction_to_mchprs(props.facing),
-                                            props.powered,
-                                        ),
-                                    }
-                                }
-                                LEVER => {
-                                    let props = LeverLikeProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::Lever {
-                                        lever: Lever::new(
-                                            match props.face {
-                                                block_properties::BlockFace::Floor => {
-                                                    mchprs_blocks::blocks::LeverFace::Floor
-                                                }
-                                                block_properties::BlockFace::Wall => {
-                                                    mchprs_blocks::blocks::LeverFace::Wall
-                                                }
-                                                block_properties::BlockFace::Ceiling => {
-                                                    mchprs_blocks::blocks::LeverFace::Ceiling
-                                                }
-                                            },
-                                            direction_to_mchprs(props.facing),
-                                            props.powered,
-                                        ),
-                                    }
-                                }
-                                STONE_PRESSURE_PLATE => {
-                                    let props =
-                                        StonePressurePlateLikeProperties::from_state_id(s.id, b);
-                                    mchprs_blocks::blocks::Block::StonePressurePlate {
-                                        powered: props.powered,
-                                    }
-                                }
-                                REDSTONE_BLOCK => mchprs_blocks::blocks::Block::RedstoneBlock {},
-                                REDSTONE_LAMP => {
-                                    let props = RedstoneLampProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::RedstoneLamp { lit: props.lit }
-                                }
-                                IRON_TRAPDOOR => {
-                                    let props = OakTrapdoorLikeProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::IronTrapdoor {
-                                        facing: direction_to_mchprs(props.facing),
-                                        half: match props.half {
-                                            block_properties::BlockHalf::Top => {
-                                                mchprs_blocks::blocks::TrapdoorHalf::Top
-                                            }
-                                            block_properties::BlockHalf::Bottom => {
-                                                mchprs_blocks::blocks::TrapdoorHalf::Bottom
-                                            }
-                                        },
-                                        powered: props.powered,
-                                    }
-                                }
-                                REDSTONE_TORCH => {
-                                    let props = RTorchProps::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::RedstoneTorch { lit: props.lit }
-                                }
-                                REDSTONE_WALL_TORCH => {
-                                    let props = RWallTorchProps::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::RedstoneWallTorch {
-                                        lit: props.lit,
-                                        facing: direction_to_mchprs(props.facing),
-                                    }
-                                }
-                                REPEATER => {
-                                    let props = RepeaterLikeProperties::from_state_id(s.id, b);
-
-                                    mchprs_blocks::blocks::Block::RedstoneRepeater {
-                                        repeater: RedstoneRepeater {
-                                            delay: 1 + props.delay.to_index() as u8,
-                                            facing: direction_to_mchprs(props.facing),
-                                            locked: props.locked,
-                                            powered: props.powered,
-                                        },
-                                    }
-                                }
-                                COMPARATOR => {
-                                    let props = ComparatorLikeProperties::from_state_id(s.id, b);
-
-                                    if let Some(entity) = world.get_block_entity(&pos).await {
-                                        if let Some(entity) = entity.as_any().downcast_ref::<pumpkin_world::block::entities::comparator::ComparatorBlockEntity>() {
-                                            plot.set_block_entity(mchprs_pos, mchprs_blocks::block_entities::BlockEntity::Comparator { output_strength: entity.output_signal.load(Ordering::Relaxed) });
-                                        }
-                                    }
-
-                                    mchprs_blocks::blocks::Block::RedstoneComparator {
-                                        comparator: RedstoneComparator::new(
-                                            direction_to_mchprs(props.facing),
-                                            match props.mode {
-                                                block_properties::ComparatorMode::Compare => {
-                                                    mchprs_blocks::blocks::ComparatorMode::Compare
-                                                }
-                                                block_properties::ComparatorMode::Subtract => {
-                                                    mchprs_blocks::blocks::ComparatorMode::Subtract
-                                                }
-                                            },
-                                            props.powered,
-                                        ),
-                                    }
-                                }
-                                BARREL => {
-                                    let props = BarrelLikeProperties::from_state_id(s.id, b);
-
-                                    if let Some(entity) = world.get_block_entity(&pos).await {
-                                        if let Some(entity) = entity.as_any().downcast_ref::<pumpkin_world::block::entities::barrel::BarrelBlockEntity>() {
-                                            let ty = mchprs_blocks::block_entities::ContainerType::Barrel;
-                                            let num_slots = entity.items.len();
-                                            let mut fullness_sum: f32 = 0.0;
-                                            // TODO: fill inventory
-                                            let inventory = Vec::new();
-
-        
-                                            for slot in &entity.items {
-                                                let slot = slot.lock().await;
-                                                let count = slot.item_count;
-                                                if count == 0 {
-                                                    continue;
-                                                }
-
-                                                let mut max_stack_size = 64;
-                                                let item = slot.item;
-                                                for component in item.components {
-                                                    if component.0 == pumpkin_data::data_component::DataComponent::MaxStackSize {
-                                                        if let Some(size) = component.1.as_any().downcast_ref::<pumpkin_data::data_component_impl::MaxStackSizeImpl>() {
-                                                            max_stack_size = size.size;
-                                                        }
-                                                    }
-                                                }
-                                                
-                                                fullness_sum += count as f32 / max_stack_size as f32;
-                                            }
-
-                                            let comparator_override = (if fullness_sum > 0.0 { 1.0 } else { 0.0 }
-                                                + (fullness_sum / num_slots as f32) * 14.0)
-                                                .floor() as u8;
-
-                                            plot.set_block_entity(mchprs_pos, mchprs_blocks::block_entities::BlockEntity::Container { comparator_override, inventory, ty });
-                                        }
-                                    }
-
-                                    mchprs_blocks::blocks::Block::Barrel {}
-                                }
-                                TARGET => mchprs_blocks::blocks::Block::Target {},
-                                _ => {
-                                    if let Some(block) =
-                                        mchprs_blocks::blocks::Block::from_name(b.name)
-                                    {
-                                        block
-                                    } else {
-                                        let solid = s.is_solid();
-
-                                        // sender.send_message(TextComponent::text(format!("Unknown block {:?}", solid))).await;
-
-                                        if solid {
-                                            mchprs_blocks::blocks::Block::IronBlock {}
-                                        } else {
-                                            mchprs_blocks::blocks::Block::Glass {}
-                                        }
-                                    }
-                                }
-                            };
+                let message = match message.strip_prefix('\n') {
+                    Some(message) => message.to_string(),
+                    None => "There are no probes here or in your circuits".to_string(),
+                };
+                sender.send_message(TextComponent::text(message)).await;
+            }
+            Command::ProbeRemove => {
+                let Some(Arg::Simple(name)) = args.get("name") else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing probe name",
+                    ))));
+                };
 
-                            plot.set_block(mchprs_pos, mchprs_block);
-                        }
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                let mut removed = false;
+                for index in targets {
+                    removed |= data.plots[index].remove_probe(name);
+                }
+                if !removed {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        format!("There is no probe named {name}"),
+                    ))));
+                }
+
+                sender
+                    .send_message(TextComponent::text(format!("Removed probe {name}")))
+                    .await;
+            }
+            Command::Record | Command::RecordStop => {
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, circuit_name);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                let ticks = match args.get("ticks") {
+                    Some(Arg::Num(Ok(Number::I32(n)))) => *n as u64,
+                    _ => 0,
+                };
+
+                for index in targets {
+                    let plot = &data.plots[index];
+                    let message = match self.cmd {
+                        Command::Record => match plot.start_recording(player_id, ticks) {
+                            Ok(probes) => format!(
+                                "Recording {probes} probes of circuit {} for {ticks} ticks",
+                                plot.info.name
+                            ),
+                            Err(err) => err,
+                        },
+                        _ => match plot.stop_recording() {
+                            Some(recording) => {
+                                recording_message(&data.data_folder, &plot.info.name, &recording)
+                            }
+                            None => format!("Circuit {} is not recording", plot.info.name),
+                        },
+                    };
+                    sender.send_message(TextComponent::text(message)).await;
+                }
+
+                data.scheduler.wake();
+            }
+            Command::Break => {
+                let (Some(Arg::Simple(probe)), Some(Arg::Msg(condition))) =
+                    (args.get("probe"), args.get("condition"))
+                else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing probe or condition",
+                    ))));
+                };
+                let condition = Condition::parse(condition).map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                })?;
+
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                let mut added = false;
+                for index in targets {
+                    let plot = &data.plots[index];
+                    if !plot.add_breakpoint(probe, player_id, condition) {
+                        continue;
+                    }
+                    added = true;
+                    sender
+                        .send_message(TextComponent::text(format!(
+                            "Circuit {} pauses when probe {probe} is {condition}",
+                            plot.info.name
+                        )))
+                        .await;
+                }
+                if !added {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        format!("There is no probe named {probe}"),
+                    ))));
+                }
+            }
+            Command::BreakList => {
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, None);
+
+                let mut message = String::new();
+                for index in targets {
+                    let plot = &data.plots[index];
+                    for breakpoint in plot.breakpoints() {
+                        message += &format!(
+                            "\nBreakpoint on probe {} {} in circuit {}",
+                            breakpoint.probe, breakpoint.condition, plot.info.name
+                        );
                     }
                 }
 
-                let min_pos = mchprs_blocks::BlockPos::new(0, 0, 0);
-                let max_pos =
-                    mchprs_blocks::BlockPos::new(plot.size_x - 1, plot.size_y - 1, plot.size_z - 1);
-
-                let mut compiler = Compiler::default();
-                let bounds = (min_pos, max_pos);
-                let options = CompilerOptions {
-                    optimize: false,
-                    io_only: false,
-                    wire_dot_out: true,
-                    backend_variant: BackendVariant::Direct,
-                    export_dot_graph: true,
-                    ..Default::default()
+                let message = match message.strip_prefix('\n') {
+                    Some(message) => message.to_string(),
+                    None => "There are no breakpoints here or in your circuits".to_string(),
+                };
+                sender.send_message(TextComponent::text(message)).await;
+            }
+            Command::BreakClear => {
+                let data = self.data.read().await;
+                let targets = data.target_plots(player_id, &world, player_pos, circuit_name);
+                require_targets(sender, &data.plots, &targets, player_id).await?;
+
+                for index in targets {
+                    let plot = &data.plots[index];
+                    let count = plot.clear_breakpoints();
+                    sender
+                        .send_message(TextComponent::text(format!(
+                            "Removed {count} breakpoints from circuit {}",
+                            plot.info.name
+                        )))
+                        .await;
+                }
+            }
+            Command::Inspect => {
+                let Some(pos) = probe::targeted_block(&world, &player).await else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        format!(
+                            "You are not looking at a block within {} blocks",
+                            probe::REACH
+                        ),
+                    ))));
                 };
-                let ticks = plot.to_be_ticked.drain(..).collect();
-                let monitor = Default::default();
-                compiler.compile(&mut plot, bounds, options, ticks, monitor);
-
-                data.plot = Some(PlotData {
-                    base: mchprs_blocks::BlockPos::new(x1, y1, z1),
-                    plot,
-                    compiler,
-                    world: world.clone(),
-                });
+
+                let plot = {
+                    let data = self.data.read().await;
+                    let Some(index) = data.plot_index_at(&world, pos) else {
+                        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                            "The block you are looking at is not part of a compiled circuit",
+                        ))));
+                    };
+                    data.plots[index].clone()
+                };
+                let graph = plot.graph().await.map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(format!(
+                        "The graph of circuit {} is not available: {err}",
+                        plot.info.name
+                    ))))
+                })?;
+
+                let local = plot.to_local(pos);
+                let (block, _) = world.get_block_and_state(&pos).await;
+                let power = match plot.unapplied_power(local) {
+                    Some(power) => Some(power),
+                    None => probe::world_power(&world, pos).await,
+                };
+                let ids = graph.nodes_at(local);
+                let at = format!("{} at {}, {}, {}", block.name, pos.0.x, pos.0.y, pos.0.z);
+
+                let message = if ids.is_empty() {
+                    format!("{at} {}", plot.without_nodes(local))
+                } else {
+                    let mut message = format!(
+                        "{at} in circuit {} was compiled into {} nodes",
+                        plot.info.name,
+                        ids.len()
+                    );
+                    for id in ids {
+                        message += "\n";
+                        message += &inspect::describe(graph, id, plot.base, power);
+                    }
+                    message
+                };
+                sender.send_message(TextComponent::text(message)).await;
+            }
+            Command::Export => {
+                let (selection, data_folder, max_volume) = {
+                    let data = self.data.read().await;
+                    let selection = data.selections.get(&player_id).copied().unwrap_or_default();
+                    (
+                        selection,
+                        data.data_folder.clone(),
+                        data.config.max_selection_volume,
+                    )
+                };
+                let (Some(p1), Some(p2)) = (selection.pos1, selection.pos2) else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Select an area with /redpiler pos1 and pos2 first",
+                    ))));
+                };
+                let Some(Arg::Simple(name)) = args.get("name") else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing schematic name",
+                    ))));
+                };
+
+                require_selection_volume(p1, p2, max_volume)?;
+                schematic::check_size(p1, p2).map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                })?;
+
+                let schematic = Schematic::from_world(&world, p1, p2).await;
+                let path = schematic::schematic_path(&data_folder, name);
+                schematic.save(&path).map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                })?;
 
                 sender
-                    .send_message(TextComponent::text(format!("Compiled successfully")))
+                    .send_message(TextComponent::text(format!(
+                        "Exported {} block types and {} block entities to {}",
+                        schematic.palette.len(),
+                        schematic.block_entities.len(),
+                        path.display()
+                    )))
                     .await;
             }
-            Command::Pos1 => {
-                let mut data = self.data.write().await;
-                data.pos1 = Some(player.position().sub_raw(0.5, 0.5, 0.5).to_block_pos());
+            Command::Import | Command::ImportAndCompile => {
+                if let Command::ImportAndCompile = self.cmd {
+                    permissions::require(sender, permissions::COMPILE).await?;
+                }
+
+                let Some(Arg::Simple(file)) = args.get("file") else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing schematic name",
+                    ))));
+                };
+
+                let (origin, path, max_volume) = {
+                    let data = self.data.read().await;
+                    let selection = data.selections.get(&player_id).copied().unwrap_or_default();
+                    (
+                        selection.pos1.unwrap_or(player_pos),
+                        schematic::schematic_path(&data.data_folder, file),
+                        data.config.max_selection_volume,
+                    )
+                };
+
+                let schematic = Schematic::load(&path, max_volume).map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                })?;
+
+                let corner = BlockPos::new(
+                    origin.0.x + schematic.width - 1,
+                    origin.0.y + schematic.height - 1,
+                    origin.0.z + schematic.length - 1,
+                );
+
+                {
+                    // Compiled circuits in the pasted area would no longer match the world
+                    let mut data = self.data.write().await;
+                    let overlapping = |plot: &PlotData| plot.overlaps(&world, origin, corner);
+                    if data
+                        .plots
+                        .iter()
+                        .any(|plot| overlapping(plot) && plot.info.owner != player_id)
+                    {
+                        permissions::require(sender, permissions::ADMIN).await?;
+                    }
+
+                    let (removed, kept) = data.plots.drain(..).partition(|plot| overlapping(plot));
+                    data.plots = kept;
+                    for plot in removed {
+                        plot.stop();
+                        log::info!("Invalidated plot {}", plot.info.name);
+                    }
+                    data.save();
+                }
+
+                schematic.paste(&world, origin).await;
+
+                {
+                    let mut data = self.data.write().await;
+                    data.selections.insert(
+                        player_id,
+                        Selection {
+                            pos1: Some(origin),
+                            pos2: Some(corner),
+                        },
+                    );
+                    data.save();
+                }
+
+                sender
+                    .send_message(TextComponent::text(format!(
+                        "Pasted {file} at {}, {}, {}",
+                        origin.0.x, origin.0.y, origin.0.z
+                    )))
+                    .await;
+
+                if let Command::ImportAndCompile = self.cmd {
+                    self.compile_selection(sender, &world, player_id, &player.gameprofile.name, "")
+                        .await?;
+                }
             }
-            Command::Pos2 => {
-                let mut data = self.data.write().await;
-                data.pos2 = Some(player.position().sub_raw(0.5, 0.5, 0.5).to_block_pos());
+            Command::Simulate => {
+                let Some(Arg::Simple(file)) = args.get("file") else {
+                    return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                        "Missing schematic name",
+                    ))));
+                };
+                let ticks = match args.get("ticks") {
+                    Some(Arg::Num(Ok(Number::I32(n)))) => *n as u64,
+                    _ => 0,
+                };
+                let inputs = match args.get("inputs") {
+                    Some(Arg::Msg(script)) => simulate::parse_inputs(script),
+                    _ => Ok(Vec::new()),
+                }
+                .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?;
+
+                let (path, config) = {
+                    let data = self.data.read().await;
+                    (
+                        schematic::schematic_path(&data.data_folder, file),
+                        data.config.clone(),
+                    )
+                };
+                let options = config::parse_compile_flags(&config.compile_flags)
+                    .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?
+                    .options;
+
+                let schematic =
+                    Schematic::load(&path, config.max_selection_volume).map_err(|err| {
+                        CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                    })?;
+
+                let input_count = inputs.len();
+                // Keep the simulation off the async runtime, it can run for a while
+                let outputs = tokio::task::spawn_blocking(move || {
+                    simulate::simulate(&schematic, options, ticks, &inputs)
+                })
+                .await
+                .map_err(|_| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(format!(
+                        "Failed to simulate {file}"
+                    ))))
+                })?
+                .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?;
+
+                let mut message = format!(
+                    "Simulated {file} for {ticks} ticks with {input_count} inputs, {} outputs",
+                    outputs.len()
+                );
+                for output in &outputs {
+                    message += &format!(
+                        "\n{} at {}, {}, {}: {}",
+                        output.name,
+                        output.pos.x,
+                        output.pos.y,
+                        output.pos.z,
+                        if output.powered { "on" } else { "off" }
+                    );
+                }
+                sender.send_message(TextComponent::text(message)).await;
             }
-            Command::Deselect => {
+            Command::Verify => {
+                let ticks = match args.get("ticks") {
+                    Some(Arg::Num(Ok(Number::I32(n)))) => *n as u64,
+                    _ => verify::DEFAULT_TICKS,
+                };
+                let inputs = match args.get("inputs") {
+                    Some(Arg::Msg(script)) => simulate::parse_inputs(script),
+                    _ => Ok(Vec::new()),
+                }
+                .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?;
+
+                let (p1, p2, config) = {
+                    let data = self.data.read().await;
+                    let selection = data.selections.get(&player_id).copied().unwrap_or_default();
+                    let (Some(p1), Some(p2)) = (selection.pos1, selection.pos2) else {
+                        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                            "Select an area with /redpiler pos1 and pos2 first",
+                        ))));
+                    };
+
+                    let (s1, s2) = verify::scratch_area(p1, p2);
+                    if data.plots.iter().any(|plot| plot.overlaps(&world, s1, s2)) {
+                        return Err(CommandError::CommandFailed(Box::new(TextComponent::text(
+                            "The scratch area next to the selection overlaps a compiled circuit",
+                        ))));
+                    }
+
+                    (p1, p2, data.config.clone())
+                };
+
+                require_selection_volume(p1, p2, config.max_selection_volume)?;
+
+                let options = config::parse_compile_flags(&config.compile_flags)
+                    .map_err(|err| CommandError::CommandFailed(Box::new(TextComponent::text(err))))?
+                    .options;
+
+                sender
+                    .send_message(TextComponent::text(format!(
+                        "Verifying {ticks} ticks next to the selection, about {} seconds",
+                        (ticks / 10).max(1)
+                    )))
+                    .await;
+
+                let mismatch = verify::verify(&world, p1, p2, options, ticks, &inputs)
+                    .await
+                    .map_err(|err| {
+                        CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                    })?;
+
+                let message = match mismatch {
+                    Some(mismatch) => {
+                        let mut message = format!(
+                            "Tick {}: {}, {}, {} is {} in vanilla but {} compiled, {} other \
+                             blocks differ",
+                            mismatch.tick,
+                            p1.0.x.min(p2.0.x) + mismatch.pos.x,
+                            p1.0.y.min(p2.0.y) + mismatch.pos.y,
+                            p1.0.z.min(p2.0.z) + mismatch.pos.z,
+                            mismatch.vanilla,
+                            mismatch.compiled,
+                            mismatch.others
+                        );
+                        if mismatch.skew > 0 {
+                            message += &format!(
+                                ". The server lagged {} game ticks past this tick before it was \
+                                 compared, so vanilla may have moved on",
+                                mismatch.skew
+                            );
+                        }
+                        message
+                    }
+                    None => format!("The compiled circuit matches vanilla for {ticks} ticks"),
+                };
+                sender.send_message(TextComponent::text(message)).await;
+            }
+            Command::Reload => {
                 let mut data = self.data.write().await;
-                data.pos1 = None;
-                data.pos2 = None;
+                let config = Config::load(&data.data_folder).map_err(|err| {
+                    CommandError::CommandFailed(Box::new(TextComponent::text(err)))
+                })?;
+
+                for plot in &data.plots {
+                    let mut rate = plot.rate();
+                    *rate = config.clamp_rate(*rate);
+                }
+                data.config = Arc::new(config);
+                data.save();
+
+                sender
+                    .send_message(TextComponent::text("Reloaded redpiler config"))
+                    .await;
             }
         }
 
         Ok(())
     }
 }
-
-fn facing_to_mchprs(face: block_properties::Facing) -> mchprs_blocks::BlockFacing {
-    match face {
-        block_properties::Facing::North => mchprs_blocks::BlockFacing::North,
-        block_properties::Facing::East => mchprs_blocks::BlockFacing::East,
-        block_properties::Facing::South => mchprs_blocks::BlockFacing::South,
-        block_properties::Facing::West => mchprs_blocks::BlockFacing::West,
-        block_properties::Facing::Up => mchprs_blocks::BlockFacing::Up,
-        block_properties::Facing::Down => mchprs_blocks::BlockFacing::Down,
-    }
-}
-
-fn direction_to_mchprs(face: HorizontalFacing) -> mchprs_blocks::BlockDirection {
-    match face {
-        HorizontalFacing::North => mchprs_blocks::BlockDirection::North,
-        HorizontalFacing::East => mchprs_blocks::BlockDirection::East,
-        HorizontalFacing::South => mchprs_blocks::BlockDirection::South,
-        HorizontalFacing::West => mchprs_blocks::BlockDirection::West,
-    }
-}
@@ -0,0 +1,108 @@
+use pumpkin::command::{CommandSender, dispatcher::CommandError};
+use pumpkin_util::{
+    PermissionLvl,
+    permission::{Permission, PermissionDefault, PermissionRegistry},
+};
+
+/// Required to see the `/redpiler` command at all, every subcommand has its own node as well.
+pub const COMMAND: &str = "redpiler:command";
+pub const COMPILE: &str = "redpiler:compile";
+pub const SELECTION: &str = "redpiler:selection";
+pub const RTPS: &str = "redpiler:rtps";
+/// Required for tick rates above `high_rtps_threshold` in the config, as those can use a full core.
+pub const RTPS_HIGH: &str = "redpiler:rtps.high";
+pub const RESET: &str = "redpiler:reset";
+pub const STEP: &str = "redpiler:step";
+pub const PROBE: &str = "redpiler:probe";
+/// Reads and writes schematic files in the plugin data folder.
+pub const SCHEMATIC: &str = "redpiler:schematic";
+/// Places a copy of the selection next to it while verifying.
+pub const VERIFY: &str = "redpiler:verify";
+pub const INSPECT: &str = "redpiler:inspect";
+pub const ADMIN: &str = "redpiler:admin";
+
+const PERMISSIONS: [(&str, &str, PermissionDefault); 12] = [
+    (
+        COMMAND,
+        "Use the /redpiler command",
+        PermissionDefault::Allow,
+    ),
+    (
+        COMPILE,
+        "Compile the selected area with redpiler",
+        PermissionDefault::Allow,
+    ),
+    (
+        SELECTION,
+        "Select an area using /redpiler pos1, pos2 and deselect",
+        PermissionDefault::Allow,
+    ),
+    (
+        RTPS,
+        "Change the tick rate of compiled circuits",
+        PermissionDefault::Allow,
+    ),
+    (
+        RTPS_HIGH,
+        "Set the tick rate of compiled circuits above the high rtps threshold",
+        PermissionDefault::Op(PermissionLvl::Two),
+    ),
+    (
+        RESET,
+        "Reset your own compiled circuit back into the world",
+        PermissionDefault::Allow,
+    ),
+    (
+        STEP,
+        "Pause, resume and step compiled circuits",
+        PermissionDefault::Allow,
+    ),
+    (
+        PROBE,
+        "Watch, record and break on the power level of blocks in compiled circuits",
+        PermissionDefault::Allow,
+    ),
+    (
+        SCHEMATIC,
+        "Export, import and simulate schematics in the redpiler data folder",
+        PermissionDefault::Op(PermissionLvl::Two),
+    ),
+    (
+        VERIFY,
+        "Compare compiled circuits against vanilla redstone in a scratch area",
+        PermissionDefault::Op(PermissionLvl::Two),
+    ),
+    (
+        INSPECT,
+        "Inspect the compiled redpiler nodes of a block",
+        PermissionDefault::Allow,
+    ),
+    (
+        ADMIN,
+        "Manage circuits compiled by other players",
+        PermissionDefault::Op(PermissionLvl::Three),
+    ),
+];
+
+pub fn register_permissions(registry: &mut PermissionRegistry) -> Result<(), String> {
+    for (node, description, default) in PERMISSIONS {
+        registry.register_permission(Permission::new(node, description, default))?;
+    }
+
+    Ok(())
+}
+
+/// Removes the nodes added by [`register_permissions`], so loading the plugin again can add them.
+pub fn unregister_permissions(registry: &mut PermissionRegistry) {
+    for (node, _, _) in PERMISSIONS {
+        registry.unregister_permission(node);
+    }
+}
+
+pub async fn require(sender: &CommandSender, node: &str) -> Result<(), CommandError> {
+    if sender.has_permission(node).await {
+        Ok(())
+    } else {
+        Err(CommandError::PermissionDenied)
+    }
+}
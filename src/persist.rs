@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use pumpkin_util::math::position::BlockPos;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::scheduler::TickRate;
+
+const STATE_FILE: &str = "circuits.toml";
+
+/// Everything needed to compile a circuit again after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitInfo {
+    pub name: String,
+    pub owner: Uuid,
+    /// Name of the Pumpkin world the circuit was compiled in
+    pub world: String,
+    pub pos1: [i32; 3],
+    pub pos2: [i32; 3],
+    /// Compile flags including the ones from the config at the time of compiling
+    pub flags: String,
+    pub rate: TickRate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSelection {
+    pub player: Uuid,
+    pub pos1: Option<[i32; 3]>,
+    pub pos2: Option<[i32; 3]>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    pub selections: Vec<SavedSelection>,
+    pub circuits: Vec<CircuitInfo>,
+}
+
+impl SavedState {
+    pub fn load(data_folder: &Path) -> Result<SavedState, String> {
+        let path = data_folder.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(SavedState::default());
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+        toml::from_str(&contents)
+            .map_err(|err| format!("Failed to parse {}: {err}", path.display()))
+    }
+
+    pub fn save(&self, data_folder: &Path) -> Result<(), String> {
+        let path = data_folder.join(STATE_FILE);
+        let contents = toml::to_string_pretty(self)
+            .map_err(|err| format!("Failed to serialize circuits: {err}"))?;
+
+        // Write to a temporary file first so a crash can't leave a half written file behind
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, contents)
+            .and_then(|_| std::fs::rename(&tmp_path, &path))
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+}
+
+pub fn pos_to_array(pos: BlockPos) -> [i32; 3] {
+    [pos.0.x, pos.0.y, pos.0.z]
+}
+
+pub fn array_to_pos([x, y, z]: [i32; 3]) -> BlockPos {
+    BlockPos::new(x, y, z)
+}
@@ -0,0 +1,243 @@
+use mchprs_blocks::blocks::Block;
+use pumpkin::entity::player::Player;
+use pumpkin_util::math::position::BlockPos;
+use uuid::Uuid;
+
+use crate::{compile, pumpkin_plot::PumpkinWorld};
+
+/// How far away `/rp probe add` finds the block the player is looking at.
+pub const REACH: f64 = 32.0;
+/// Height of the eyes of a standing player above their feet.
+const EYE_HEIGHT: f64 = 1.62;
+
+/// A block in a compiled circuit whose power level is reported to the player that added it.
+#[derive(Clone)]
+pub struct Probe {
+    pub name: String,
+    pub owner: Uuid,
+    /// Position relative to the lowest corner of the circuit
+    pub pos: mchprs_blocks::BlockPos,
+    /// The power level last reported
+    pub power: u8,
+}
+
+/// A probe whose power level changed.
+pub struct ProbeChange {
+    pub name: String,
+    pub owner: Uuid,
+    pub from: u8,
+    pub to: u8,
+}
+
+/// The power level shown by a block: the power of wire, 15 for powered components and lit lamps
+/// and torches, `None` for blocks without one.
+///
+/// Comparators keep their output strength in their block entity, which the compiler only writes
+/// when the circuit is reset, so they are `None` as well.
+pub fn power_level(block: Block) -> Option<u8> {
+    let powered = match block {
+        Block::RedstoneWire { wire } => return Some(wire.power),
+        Block::RedstoneBlock {} => true,
+        Block::RedstoneRepeater { repeater } => repeater.powered,
+        Block::RedstoneTorch { lit }
+        | Block::RedstoneWallTorch { lit, .. }
+        | Block::RedstoneLamp { lit } => lit,
+        Block::Lever { lever } => lever.powered,
+        Block::StoneButton { button } => button.powered,
+        Block::StonePressurePlate { powered } | Block::IronTrapdoor { powered, .. } => powered,
+        _ => return None,
+    };
+    Some(if powered { 15 } else { 0 })
+}
+
+/// The power level of the block at `pos` as it is shown in the world, see [`power_level`].
+pub async fn world_power(world: &pumpkin::world::World, pos: BlockPos) -> Option<u8> {
+    let (block, state) = world.get_block_and_state(&pos).await;
+    power_level(compile::to_mchprs_block(block, state))
+}
+
+/// The power level at `pos` after the changes the compiler flushed into `world`, `None` when it
+/// did not change.
+pub fn flushed_power(world: &PumpkinWorld, pos: mchprs_blocks::BlockPos) -> Option<u8> {
+    world
+        .set_events
+        .get(&pos)
+        .and_then(|&block| power_level(Block::from_id(block)))
+}
+
+/// Updates the probes to the changes the compiler flushed into `world`, returning the probes whose
+/// power level changed.
+pub fn observe(probes: &mut [Probe], world: &PumpkinWorld) -> Vec<ProbeChange> {
+    let mut changes = Vec::new();
+    for probe in probes {
+        let Some(power) = flushed_power(world, probe.pos) else {
+            continue;
+        };
+        if power != probe.power {
+            changes.push(ProbeChange {
+                name: probe.name.clone(),
+                owner: probe.owner,
+                from: probe.power,
+                to: power,
+            });
+            probe.power = power;
+        }
+    }
+    changes
+}
+
+/// The first block that is not air within [`REACH`] blocks of the eyes of the player.
+pub async fn targeted_block(world: &pumpkin::world::World, player: &Player) -> Option<BlockPos> {
+    let position = player.position();
+    let entity = &player.living_entity.entity;
+    let eye = (position.x, position.y + EYE_HEIGHT, position.z);
+
+    for pos in blocks_along_ray(eye, entity.yaw.load(), entity.pitch.load(), REACH) {
+        let (block, _) = world.get_block_and_state(&pos).await;
+        if block.id != pumpkin_data::Block::AIR.id {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+/// The blocks a ray from `eye` in the direction of `yaw` and `pitch` passes through, in order,
+/// until `reach` blocks away.
+pub fn blocks_along_ray(eye: (f64, f64, f64), yaw: f32, pitch: f32, reach: f64) -> Vec<BlockPos> {
+    let yaw = f64::from(yaw).to_radians();
+    let pitch = f64::from(pitch).to_radians();
+    let origin = [eye.0, eye.1, eye.2];
+    let direction = [
+        -yaw.sin() * pitch.cos(),
+        -pitch.sin(),
+        yaw.cos() * pitch.cos(),
+    ];
+
+    let mut block = origin.map(|coord| coord.floor() as i32);
+    let mut step = [0; 3];
+    // Distance along the ray between crossing two block boundaries on each axis
+    let mut delta = [f64::INFINITY; 3];
+    // Distance along the ray until the next block boundary on each axis
+    let mut next = [f64::INFINITY; 3];
+    for (axis, &direction) in direction.iter().enumerate() {
+        if direction == 0.0 {
+            continue;
+        }
+        delta[axis] = direction.recip().abs();
+        if direction > 0.0 {
+            step[axis] = 1;
+            next[axis] = (f64::from(block[axis]) + 1.0 - origin[axis]) * delta[axis];
+        } else {
+            step[axis] = -1;
+            next[axis] = (origin[axis] - f64::from(block[axis])) * delta[axis];
+        }
+    }
+
+    let mut blocks = vec![BlockPos::new(block[0], block[1], block[2])];
+    loop {
+        let axis = (0..3)
+            .min_by(|&a, &b| next[a].total_cmp(&next[b]))
+            .unwrap_or_default();
+        if next[axis] > reach {
+            return blocks;
+        }
+        block[axis] += step[axis];
+        next[axis] += delta[axis];
+        blocks.push(BlockPos::new(block[0], block[1], block[2]));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use mchprs_blocks::blocks::{RedstoneWire, RedstoneWireSide};
+
+    use super::*;
+
+    /// A probe owned by nobody, shared with the tests of the modules that use probes.
+    pub(crate) fn probe(name: &str, pos: mchprs_blocks::BlockPos, power: u8) -> Probe {
+        Probe {
+            name: name.to_string(),
+            owner: Uuid::nil(),
+            pos,
+            power,
+        }
+    }
+
+    #[test]
+    fn follows_the_look_direction() {
+        let down = blocks_along_ray((0.5, 65.62, 0.5), 0.0, 90.0, 2.0);
+        assert_eq!(
+            down,
+            [
+                BlockPos::new(0, 65, 0),
+                BlockPos::new(0, 64, 0),
+                BlockPos::new(0, 63, 0)
+            ]
+        );
+
+        // A yaw of 0 looks along +z, 90 along -x
+        let south = blocks_along_ray((0.5, 64.5, 0.5), 0.0, 0.0, 1.0);
+        assert_eq!(south.last(), Some(&BlockPos::new(0, 64, 1)));
+        let west = blocks_along_ray((0.5, 64.5, 0.5), 90.0, 0.0, 1.0);
+        assert_eq!(west.last(), Some(&BlockPos::new(-1, 64, 0)));
+    }
+
+    #[test]
+    fn crosses_every_block_on_a_diagonal() {
+        let blocks = blocks_along_ray((0.5, 64.5, 0.5), -45.0, 0.0, 3.0);
+
+        assert_eq!(blocks.len(), 5);
+        for pair in blocks.windows(2) {
+            let distance = (pair[0].0.x - pair[1].0.x).abs() + (pair[0].0.z - pair[1].0.z).abs();
+            assert_eq!(distance, 1, "skipped a block between {pair:?}");
+        }
+    }
+
+    #[test]
+    fn reads_power_levels() {
+        let side = RedstoneWireSide::None;
+        let wire = RedstoneWire::new(side, side, side, side, 7);
+        assert_eq!(power_level(Block::RedstoneWire { wire }), Some(7));
+        assert_eq!(power_level(Block::RedstoneLamp { lit: true }), Some(15));
+        assert_eq!(power_level(Block::RedstoneTorch { lit: false }), Some(0));
+        assert_eq!(power_level(Block::IronBlock {}), None);
+    }
+
+    #[test]
+    fn reports_changed_probes() {
+        let lamp = mchprs_blocks::BlockPos::new(0, 0, 0);
+        let wire = mchprs_blocks::BlockPos::new(1, 0, 0);
+        let unchanged = mchprs_blocks::BlockPos::new(2, 0, 0);
+        let mut probes = [
+            probe("lamp", lamp, 0),
+            probe("wire", wire, 3),
+            probe("unchanged", unchanged, 15),
+        ];
+
+        let side = RedstoneWireSide::None;
+        let mut world = PumpkinWorld::new(lamp);
+        world
+            .set_events
+            .insert(lamp, Block::RedstoneLamp { lit: true }.get_id());
+        world.set_events.insert(
+            wire,
+            Block::RedstoneWire {
+                wire: RedstoneWire::new(side, side, side, side, 9),
+            }
+            .get_id(),
+        );
+        world
+            .set_events
+            .insert(unchanged, Block::RedstoneTorch { lit: true }.get_id());
+
+        let changes = observe(&mut probes, &world);
+
+        let changes: Vec<_> = changes
+            .iter()
+            .map(|change| (change.from, change.to))
+            .collect();
+        assert_eq!(changes, [(0, 15), (3, 9)]);
+        assert_eq!(probes[1].power, 9);
+        assert!(observe(&mut probes, &world).is_empty());
+    }
+}
@@ -1,9 +1,13 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, atomic::Ordering},
+};
 
 use mchprs_blocks::{
     BlockPos,
     blocks::{Block, RedstoneWireSide},
 };
+use mchprs_world::TickPriority;
 use pumpkin;
 use pumpkin_data::block_properties::{
     self, BlockProperties, ComparatorLikeProperties, EastWireConnection, EnumVariants,
@@ -11,37 +15,45 @@ use pumpkin_data::block_properties::{
     OakTrapdoorLikeProperties, RepeaterLikeProperties, SouthWireConnection,
     StonePressurePlateLikeProperties, WestWireConnection,
 };
-use pumpkin_world::world::BlockFlags;
+use pumpkin_world::{block::entities::comparator::ComparatorBlockEntity, world::BlockFlags};
 
 use crate::{RTorchProps, RWallTorchProps, RedstoneLampProperties, RedstoneWireProperties};
 
+/// The Pumpkin block at each local position of a compiled circuit.
+pub type BlockCache = HashMap<BlockPos, &'static pumpkin_data::Block>;
+
 pub struct PumpkinWorld {
     pub base: BlockPos,
-    pub set_events: Vec<(BlockPos, u32)>,
-    pub entities: Vec<(BlockPos, mchprs_blocks::block_entities::BlockEntity)>,
+    /// The last block set at each position, earlier changes within a flush are never visible
+    pub set_events: HashMap<BlockPos, u32>,
+    pub entities: HashMap<BlockPos, mchprs_blocks::block_entities::BlockEntity>,
+    pub ticks: Vec<(BlockPos, u32, TickPriority)>,
 }
 
-
+/// Only records what the compiler flushes, so reads see those changes and nothing else.
 impl mchprs_world::World for PumpkinWorld {
     fn get_block_raw(&self, pos: BlockPos) -> u32 {
-        todo!()
+        self.set_events
+            .get(&pos)
+            .copied()
+            .unwrap_or_else(|| Block::Air {}.get_id())
     }
 
     fn set_block_raw(&mut self, pos: BlockPos, block: u32) -> bool {
-        self.set_events.push((pos, block));
+        self.set_events.insert(pos, block);
 
         return true;
     }
 
     fn delete_block_entity(&mut self, pos: BlockPos) {
-        todo!()
+        self.entities.remove(&pos);
     }
 
     fn get_block_entity(
         &self,
         pos: BlockPos,
     ) -> Option<&mchprs_blocks::block_entities::BlockEntity> {
-        todo!()
+        self.entities.get(&pos)
     }
 
     fn set_block_entity(
@@ -49,23 +61,23 @@ impl mchprs_world::World for PumpkinWorld {
         pos: BlockPos,
         block_entity: mchprs_blocks::block_entities::BlockEntity,
     ) {
-        todo!()
+        self.entities.insert(pos, block_entity);
     }
 
-    fn get_chunk(&self, x: i32, z: i32) -> Option<&mchprs_world::storage::Chunk> {
-        todo!()
+    fn get_chunk(&self, _x: i32, _z: i32) -> Option<&mchprs_world::storage::Chunk> {
+        None
     }
 
-    fn get_chunk_mut(&mut self, x: i32, z: i32) -> Option<&mut mchprs_world::storage::Chunk> {
-        todo!()
+    fn get_chunk_mut(&mut self, _x: i32, _z: i32) -> Option<&mut mchprs_world::storage::Chunk> {
+        None
     }
 
-    fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: mchprs_world::TickPriority) {
-        todo!()
+    fn schedule_tick(&mut self, pos: BlockPos, delay: u32, priority: TickPriority) {
+        self.ticks.push((pos, delay, priority));
     }
 
     fn pending_tick_at(&mut self, pos: BlockPos) -> bool {
-        todo!()
+        self.ticks.iter().any(|&(tick_pos, _, _)| tick_pos == pos)
     }
 }
 
@@ -73,161 +85,243 @@ impl PumpkinWorld {
     pub fn new(base: BlockPos) -> Self {
         PumpkinWorld {
             base,
-            set_events: Vec::new(),
-            entities: Vec::new(),
+            set_events: HashMap::new(),
+            entities: HashMap::new(),
+            ticks: Vec::new(),
+        }
+    }
+
+    /// Moves the changes to blocks that are not inputs or outputs of the circuit into `hidden`,
+    /// replacing older hidden changes at the same position.
+    pub fn retain_io(&mut self, hidden: &mut HashMap<BlockPos, u32>) {
+        self.set_events.retain(|&pos, &mut block| {
+            let io = is_io_block(Block::from_id(block));
+            if !io {
+                hidden.insert(pos, block);
+            }
+            io
+        });
+    }
+
+    /// Adds the changes moved out by [`PumpkinWorld::retain_io`], unless this has a newer change
+    /// at the same position.
+    pub fn restore_hidden(&mut self, hidden: &mut HashMap<BlockPos, u32>) {
+        for (pos, block) in hidden.drain() {
+            self.set_events.entry(pos).or_insert(block);
         }
     }
 
-    pub async fn apply(&mut self, world: Arc<pumpkin::world::World>) {
-        for (pos, block) in self.set_events.drain(..) {
-            let pumpkin_pos = pumpkin_util::math::position::BlockPos::new(
-                self.base.x + pos.x,
-                self.base.y + pos.y,
-                self.base.z + pos.z,
-            );
+    /// Writes the changes to the world, `applied` holds the state last written to each position and
+    /// is used to skip blocks that already look the same to clients.
+    pub async fn apply(
+        &mut self,
+        world: Arc<pumpkin::world::World>,
+        blocks: &BlockCache,
+        applied: &mut HashMap<BlockPos, u16>,
+    ) {
+        for (pos, block) in self.set_events.drain() {
+            let pumpkin_pos = self.to_pumpkin_pos(pos);
 
             let block = Block::from_id(block);
 
-            let pumpkin_block = world.get_block(&pumpkin_pos).await;
-
-            let state = match block {
-                Block::RedstoneWire { wire } => RedstoneWireProperties {
-                    north: match wire.north {
-                        RedstoneWireSide::Up => NorthWireConnection::Up,
-                        RedstoneWireSide::Side => NorthWireConnection::Side,
-                        RedstoneWireSide::None => NorthWireConnection::None,
-                    },
-                    south: match wire.south {
-                        RedstoneWireSide::Up => SouthWireConnection::Up,
-                        RedstoneWireSide::Side => SouthWireConnection::Side,
-                        RedstoneWireSide::None => SouthWireConnection::None,
-                    },
-                    east: match wire.east {
-                        RedstoneWireSide::Up => EastWireConnection::Up,
-                        RedstoneWireSide::Side => EastWireConnection::Side,
-                        RedstoneWireSide::None => EastWireConnection::None,
-                    },
-                    west: match wire.west {
-                        RedstoneWireSide::Up => WestWireConnection::Up,
-                        RedstoneWireSide::Side => WestWireConnection::Side,
-                        RedstoneWireSide::None => WestWireConnection::None,
-                    },
-                    power: pumpkin_data::block_properties::Integer0To15::from_index(
-                        wire.power as u16,
-                    ),
-                }
-                .to_state_id(&pumpkin_block),
-                Block::Lever { lever } => LeverLikeProperties {
-                    face: match lever.face {
-                        mchprs_blocks::blocks::LeverFace::Floor => {
-                            block_properties::BlockFace::Floor
-                        }
-                        mchprs_blocks::blocks::LeverFace::Wall => block_properties::BlockFace::Wall,
-                        mchprs_blocks::blocks::LeverFace::Ceiling => {
-                            block_properties::BlockFace::Ceiling
-                        }
-                    },
-                    facing: direction_to_pumpkin(lever.facing),
-                    powered: lever.powered,
-                }
-                .to_state_id(pumpkin_block),
-                Block::StoneButton { button } => LeverLikeProperties {
-                    face: match button.face {
-                        mchprs_blocks::blocks::ButtonFace::Floor => {
-                            block_properties::BlockFace::Floor
-                        }
-                        mchprs_blocks::blocks::ButtonFace::Wall => {
-                            block_properties::BlockFace::Wall
-                        }
-                        mchprs_blocks::blocks::ButtonFace::Ceiling => {
-                            block_properties::BlockFace::Ceiling
-                        }
-                    },
-                    facing: direction_to_pumpkin(button.facing),
-                    powered: button.powered,
-                }
-                .to_state_id(pumpkin_block),
-                Block::RedstoneTorch { lit } => RTorchProps { lit }.to_state_id(pumpkin_block),
-                Block::RedstoneWallTorch { lit, facing } => RWallTorchProps {
-                    facing: direction_to_pumpkin(facing),
-                    lit,
-                }
-                .to_state_id(pumpkin_block),
-                Block::RedstoneRepeater { repeater } => RepeaterLikeProperties {
-                    delay: block_properties::Integer1To4::from_index(repeater.delay as u16 - 1),
-                    facing: direction_to_pumpkin(repeater.facing),
-                    locked: repeater.locked,
-                    powered: repeater.powered,
-                }
-                .to_state_id(pumpkin_block),
-                Block::RedstoneLamp { lit } => {
-                    RedstoneLampProperties { lit }.to_state_id(pumpkin_block)
-                }
-                Block::IronTrapdoor {
-                    facing,
-                    half,
-                    powered,
-                } => OakTrapdoorLikeProperties {
-                    facing: direction_to_pumpkin(facing),
-                    half: match half {
-                        mchprs_blocks::blocks::TrapdoorHalf::Top => {
-                            block_properties::BlockHalf::Top
-                        }
-                        mchprs_blocks::blocks::TrapdoorHalf::Bottom => {
-                            block_properties::BlockHalf::Bottom
-                        }
-                    },
-                    open: powered,
-                    powered,
-                    waterlogged: false,
-                }
-                .to_state_id(pumpkin_block),
-                Block::NoteBlock {
-                    instrument,
-                    note,
-                    powered,
-                } => todo!(),
-                Block::StonePressurePlate { powered } => {
-                    StonePressurePlateLikeProperties { powered }.to_state_id(pumpkin_block)
-                }
-                Block::Observer { facing } => todo!(),
-                Block::RedstoneComparator { comparator } => ComparatorLikeProperties {
-                    facing: direction_to_pumpkin(comparator.facing),
-                    mode: match comparator.mode {
-                        mchprs_blocks::blocks::ComparatorMode::Compare => {
-                            block_properties::ComparatorMode::Compare
-                        }
-                        mchprs_blocks::blocks::ComparatorMode::Subtract => {
-                            block_properties::ComparatorMode::Subtract
-                        }
-                    },
-                    powered: comparator.powered,
-                }
-                .to_state_id(pumpkin_block),
-                _ => continue,
+            let Some(pumpkin_block) = blocks.get(&pos).copied() else {
+                continue;
+            };
+
+            let Some(state) = to_pumpkin_state(block, pumpkin_block) else {
+                continue;
             };
 
-            // println!("updating block at {:?} to {:?}", pumpkin_pos, block);
+            if applied.insert(pos, state) == Some(state) {
+                continue;
+            }
 
             world
                 .set_block_state(&pumpkin_pos, state, BlockFlags::empty())
                 .await;
         }
 
-        for (pos, entity) in self.entities.drain(..) {
-            // println!("updating block entity at {:?} to {:?}", pos, entity);
+        // Send the changes right away as one multi block update per chunk section, instead of
+        // waiting for the next server tick
+        world.flush_block_updates().await;
+
+        for (pos, entity) in self.entities.drain() {
+            // The compiler only changes the output strength of comparators, containers keep their
+            // items while compiled
+            let mchprs_blocks::block_entities::BlockEntity::Comparator { output_strength } = entity
+            else {
+                continue;
+            };
+
+            let pumpkin_pos = self.to_pumpkin_pos(pos);
+            match world.get_block_entity(&pumpkin_pos).await {
+                Some(entity) => {
+                    if let Some(comparator) =
+                        entity.as_any().downcast_ref::<ComparatorBlockEntity>()
+                    {
+                        comparator
+                            .output_signal
+                            .store(output_strength, Ordering::Relaxed);
+                    }
+                }
+                None => {
+                    let comparator = ComparatorBlockEntity::new(pumpkin_pos);
+                    comparator
+                        .output_signal
+                        .store(output_strength, Ordering::Relaxed);
+                    world.add_block_entity(Arc::new(comparator)).await;
+                }
+            }
+        }
+
+        // Pending ticks are only handed back when a circuit is reset
+        for (pos, delay, priority) in self.ticks.drain(..) {
+            let Some(block) = blocks.get(&pos).copied() else {
+                continue;
+            };
+
+            world
+                .schedule_block_tick(
+                    block,
+                    self.to_pumpkin_pos(pos),
+                    delay as u16,
+                    tick_priority_to_pumpkin(priority),
+                )
+                .await;
         }
     }
+
+    fn to_pumpkin_pos(&self, pos: BlockPos) -> pumpkin_util::math::position::BlockPos {
+        pumpkin_util::math::position::BlockPos::new(
+            self.base.x + pos.x,
+            self.base.y + pos.y,
+            self.base.z + pos.z,
+        )
+    }
 }
 
-fn facing_to_pumpkin(face: mchprs_blocks::BlockFacing) -> block_properties::Facing {
-    match face {
-        mchprs_blocks::BlockFacing::North => block_properties::Facing::North,
-        mchprs_blocks::BlockFacing::East => block_properties::Facing::East,
-        mchprs_blocks::BlockFacing::South => block_properties::Facing::South,
-        mchprs_blocks::BlockFacing::West => block_properties::Facing::West,
-        mchprs_blocks::BlockFacing::Up => block_properties::Facing::Up,
-        mchprs_blocks::BlockFacing::Down => block_properties::Facing::Down,
+/// Maps a block from the compiler back to the state of `pumpkin_block`, `None` for blocks the
+/// compiler never changes and for note blocks and observers, which it can't map back without
+/// losing their instrument, note or power.
+pub fn to_pumpkin_state(block: Block, pumpkin_block: &pumpkin_data::Block) -> Option<u16> {
+    let state = match block {
+        Block::RedstoneWire { wire } => RedstoneWireProperties {
+            north: match wire.north {
+                RedstoneWireSide::Up => NorthWireConnection::Up,
+                RedstoneWireSide::Side => NorthWireConnection::Side,
+                RedstoneWireSide::None => NorthWireConnection::None,
+            },
+            south: match wire.south {
+                RedstoneWireSide::Up => SouthWireConnection::Up,
+                RedstoneWireSide::Side => SouthWireConnection::Side,
+                RedstoneWireSide::None => SouthWireConnection::None,
+            },
+            east: match wire.east {
+                RedstoneWireSide::Up => EastWireConnection::Up,
+                RedstoneWireSide::Side => EastWireConnection::Side,
+                RedstoneWireSide::None => EastWireConnection::None,
+            },
+            west: match wire.west {
+                RedstoneWireSide::Up => WestWireConnection::Up,
+                RedstoneWireSide::Side => WestWireConnection::Side,
+                RedstoneWireSide::None => WestWireConnection::None,
+            },
+            power: pumpkin_data::block_properties::Integer0To15::from_index(wire.power as u16),
+        }
+        .to_state_id(pumpkin_block),
+        Block::Lever { lever } => LeverLikeProperties {
+            face: match lever.face {
+                mchprs_blocks::blocks::LeverFace::Floor => block_properties::BlockFace::Floor,
+                mchprs_blocks::blocks::LeverFace::Wall => block_properties::BlockFace::Wall,
+                mchprs_blocks::blocks::LeverFace::Ceiling => block_properties::BlockFace::Ceiling,
+            },
+            facing: direction_to_pumpkin(lever.facing),
+            powered: lever.powered,
+        }
+        .to_state_id(pumpkin_block),
+        Block::StoneButton { button } => LeverLikeProperties {
+            face: match button.face {
+                mchprs_blocks::blocks::ButtonFace::Floor => block_properties::BlockFace::Floor,
+                mchprs_blocks::blocks::ButtonFace::Wall => block_properties::BlockFace::Wall,
+                mchprs_blocks::blocks::ButtonFace::Ceiling => block_properties::BlockFace::Ceiling,
+            },
+            facing: direction_to_pumpkin(button.facing),
+            powered: button.powered,
+        }
+        .to_state_id(pumpkin_block),
+        Block::RedstoneTorch { lit } => RTorchProps { lit }.to_state_id(pumpkin_block),
+        Block::RedstoneWallTorch { lit, facing } => RWallTorchProps {
+            facing: direction_to_pumpkin(facing),
+            lit,
+        }
+        .to_state_id(pumpkin_block),
+        Block::RedstoneRepeater { repeater } => RepeaterLikeProperties {
+            delay: block_properties::Integer1To4::from_index(repeater.delay as u16 - 1),
+            facing: direction_to_pumpkin(repeater.facing),
+            locked: repeater.locked,
+            powered: repeater.powered,
+        }
+        .to_state_id(pumpkin_block),
+        Block::RedstoneLamp { lit } => RedstoneLampProperties { lit }.to_state_id(pumpkin_block),
+        Block::IronTrapdoor {
+            facing,
+            half,
+            powered,
+        } => OakTrapdoorLikeProperties {
+            facing: direction_to_pumpkin(facing),
+            half: match half {
+                mchprs_blocks::blocks::TrapdoorHalf::Top => block_properties::BlockHalf::Top,
+                mchprs_blocks::blocks::TrapdoorHalf::Bottom => block_properties::BlockHalf::Bottom,
+            },
+            open: powered,
+            powered,
+            waterlogged: false,
+        }
+        .to_state_id(pumpkin_block),
+        Block::StonePressurePlate { powered } => {
+            StonePressurePlateLikeProperties { powered }.to_state_id(pumpkin_block)
+        }
+        Block::RedstoneComparator { comparator } => ComparatorLikeProperties {
+            facing: direction_to_pumpkin(comparator.facing),
+            mode: match comparator.mode {
+                mchprs_blocks::blocks::ComparatorMode::Compare => {
+                    block_properties::ComparatorMode::Compare
+                }
+                mchprs_blocks::blocks::ComparatorMode::Subtract => {
+                    block_properties::ComparatorMode::Subtract
+                }
+            },
+            powered: comparator.powered,
+        }
+        .to_state_id(pumpkin_block),
+        _ => return None,
+    };
+    Some(state)
+}
+
+/// Whether players interact with or look at the block, as opposed to wiring in between.
+fn is_io_block(block: Block) -> bool {
+    is_input_block(block)
+        || matches!(
+            block,
+            Block::RedstoneLamp { .. } | Block::IronTrapdoor { .. } | Block::NoteBlock { .. }
+        )
+}
+
+/// Whether the block is a lever, button or pressure plate, the only blocks the compiler can use.
+pub fn is_input_block(block: Block) -> bool {
+    matches!(
+        block,
+        Block::Lever { .. } | Block::StoneButton { .. } | Block::StonePressurePlate { .. }
+    )
+}
+
+fn tick_priority_to_pumpkin(priority: TickPriority) -> pumpkin_world::tick::TickPriority {
+    match priority {
+        TickPriority::Highest => pumpkin_world::tick::TickPriority::ExtremelyHigh,
+        TickPriority::Higher => pumpkin_world::tick::TickPriority::VeryHigh,
+        TickPriority::High => pumpkin_world::tick::TickPriority::High,
+        TickPriority::Normal => pumpkin_world::tick::TickPriority::Normal,
     }
 }
 
@@ -239,3 +333,215 @@ fn direction_to_pumpkin(face: mchprs_blocks::BlockDirection) -> HorizontalFacing
         mchprs_blocks::BlockDirection::West => HorizontalFacing::West,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mchprs_world::World;
+    use pumpkin_data::BlockState;
+
+    use super::*;
+    use crate::compile;
+
+    #[test]
+    fn reads_back_recorded_changes() {
+        let mut world = PumpkinWorld::new(BlockPos::new(0, 0, 0));
+        let pos = BlockPos::new(1, 2, 3);
+        let lamp = Block::RedstoneLamp { lit: true };
+
+        assert_eq!(world.get_block(pos), Block::Air {});
+        world.set_block(pos, lamp);
+        world.schedule_tick(pos, 2, TickPriority::Normal);
+
+        assert_eq!(world.get_block(pos), lamp);
+        assert!(world.pending_tick_at(pos));
+        assert!(!world.pending_tick_at(BlockPos::new(0, 0, 0)));
+        assert!(world.get_block_entity(pos).is_none());
+    }
+
+    /// Maps a Pumpkin state to the compiler and back, which must give the same state.
+    fn assert_round_trip(block: &'static pumpkin_data::Block, state: u16) {
+        let mapped = compile::to_mchprs_block(block, BlockState::from_id(state));
+        assert_eq!(
+            to_pumpkin_state(mapped, block),
+            Some(state),
+            "{} mapped to {mapped:?}",
+            block.name
+        );
+    }
+
+    #[test]
+    fn round_trips_wire() {
+        let block = &pumpkin_data::Block::REDSTONE_WIRE;
+        let state = RedstoneWireProperties {
+            north: NorthWireConnection::Side,
+            south: SouthWireConnection::Up,
+            east: EastWireConnection::None,
+            west: WestWireConnection::Side,
+            power: block_properties::Integer0To15::from_index(7),
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+    }
+
+    #[test]
+    fn round_trips_diodes() {
+        let block = &pumpkin_data::Block::REPEATER;
+        let state = RepeaterLikeProperties {
+            delay: block_properties::Integer1To4::from_index(3),
+            facing: HorizontalFacing::West,
+            locked: false,
+            powered: true,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+
+        let block = &pumpkin_data::Block::COMPARATOR;
+        let state = ComparatorLikeProperties {
+            facing: HorizontalFacing::North,
+            mode: block_properties::ComparatorMode::Subtract,
+            powered: true,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+    }
+
+    #[test]
+    fn round_trips_torches() {
+        let block = &pumpkin_data::Block::REDSTONE_TORCH;
+        assert_round_trip(block, RTorchProps { lit: false }.to_state_id(block));
+
+        let block = &pumpkin_data::Block::REDSTONE_WALL_TORCH;
+        let state = RWallTorchProps {
+            facing: HorizontalFacing::East,
+            lit: true,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+    }
+
+    #[test]
+    fn round_trips_inputs() {
+        let block = &pumpkin_data::Block::LEVER;
+        let state = LeverLikeProperties {
+            face: block_properties::BlockFace::Ceiling,
+            facing: HorizontalFacing::South,
+            powered: true,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+
+        let block = &pumpkin_data::Block::STONE_BUTTON;
+        let state = LeverLikeProperties {
+            face: block_properties::BlockFace::Wall,
+            facing: HorizontalFacing::East,
+            powered: false,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+
+        let block = &pumpkin_data::Block::STONE_PRESSURE_PLATE;
+        let state = StonePressurePlateLikeProperties { powered: true }.to_state_id(block);
+        assert_round_trip(block, state);
+    }
+
+    #[test]
+    fn round_trips_outputs() {
+        let block = &pumpkin_data::Block::REDSTONE_LAMP;
+        let state = RedstoneLampProperties { lit: true }.to_state_id(block);
+        assert_round_trip(block, state);
+
+        // Trapdoors are open exactly when powered while compiled
+        let block = &pumpkin_data::Block::IRON_TRAPDOOR;
+        let state = OakTrapdoorLikeProperties {
+            facing: HorizontalFacing::South,
+            half: block_properties::BlockHalf::Top,
+            open: true,
+            powered: true,
+            waterlogged: false,
+        }
+        .to_state_id(block);
+        assert_round_trip(block, state);
+    }
+
+    #[test]
+    fn skips_blocks_the_compiler_never_changes() {
+        assert_eq!(
+            to_pumpkin_state(Block::IronBlock {}, &pumpkin_data::Block::IRON_BLOCK),
+            None
+        );
+    }
+
+    #[test]
+    fn skips_note_blocks_and_observers() {
+        let note_block = Block::from_name("note_block").unwrap();
+        assert_eq!(
+            to_pumpkin_state(note_block, &pumpkin_data::Block::NOTE_BLOCK),
+            None
+        );
+        assert_eq!(
+            to_pumpkin_state(
+                Block::Observer {
+                    facing: mchprs_blocks::BlockFacing::Up
+                },
+                &pumpkin_data::Block::OBSERVER
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn uses_only_levers_buttons_and_pressure_plates() {
+        assert!(is_input_block(Block::StonePressurePlate { powered: false }));
+        assert!(!is_input_block(Block::RedstoneLamp { lit: false }));
+        assert!(!is_input_block(Block::IronBlock {}));
+    }
+
+    #[test]
+    fn retains_only_io_blocks() {
+        let mut world = PumpkinWorld::new(BlockPos::new(0, 0, 0));
+        world.set_events.insert(
+            BlockPos::new(0, 0, 0),
+            Block::RedstoneLamp { lit: true }.get_id(),
+        );
+        world.set_events.insert(
+            BlockPos::new(1, 0, 0),
+            Block::RedstoneTorch { lit: true }.get_id(),
+        );
+
+        let mut hidden = HashMap::new();
+        world.retain_io(&mut hidden);
+
+        assert_eq!(world.set_events.len(), 1);
+        assert!(world.set_events.contains_key(&BlockPos::new(0, 0, 0)));
+        assert_eq!(
+            hidden.get(&BlockPos::new(1, 0, 0)),
+            Some(&Block::RedstoneTorch { lit: true }.get_id())
+        );
+    }
+
+    #[test]
+    fn restores_hidden_changes_older_than_the_flush() {
+        let changed = BlockPos::new(0, 0, 0);
+        let unchanged = BlockPos::new(1, 0, 0);
+        let mut hidden = HashMap::from([
+            (changed, Block::RedstoneTorch { lit: true }.get_id()),
+            (unchanged, Block::RedstoneTorch { lit: true }.get_id()),
+        ]);
+        let mut world = PumpkinWorld::new(changed);
+        world
+            .set_events
+            .insert(changed, Block::RedstoneTorch { lit: false }.get_id());
+
+        world.restore_hidden(&mut hidden);
+
+        assert!(hidden.is_empty());
+        assert_eq!(
+            world.set_events[&changed],
+            Block::RedstoneTorch { lit: false }.get_id()
+        );
+        assert_eq!(
+            world.set_events[&unchanged],
+            Block::RedstoneTorch { lit: true }.get_id()
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, time::Instant};
+
+/// The tick loop never sleeps shorter than this, faster circuits run multiple ticks per wake up.
+pub const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// How often circuits in [`TickRate::Sync`] check whether the server ticked.
+pub const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a circuit in [`TickRate::Unlimited`] ticks before the tick loop moves on.
+pub const UNLIMITED_TIME_SLICE: Duration = Duration::from_millis(10);
+/// Number of ticks a circuit runs at once while its simulation is locked, commands that need the
+/// simulation get it in between. Circuits in [`TickRate::Unlimited`] check their time slice
+/// after every batch.
+pub const TICK_BATCH: u64 = 100;
+
+/// How long the achieved tick rate of a circuit is averaged over.
+const MEASURE_WINDOW: Duration = Duration::from_secs(1);
+/// Minimum time between two warnings about the same circuit falling behind.
+const WARNING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the tick loop does with the ticks it couldn't run in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    /// Drop the ticks that are behind
+    Skip,
+    /// Run the ticks that are behind, up to `max_catch_up_ticks` at once
+    CatchUp,
+    /// Drop the ticks that are behind and lower the rtps to what the circuit can keep up with
+    LowerRtps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TickRate {
+    /// Redstone ticks per second of wall clock time
+    Rtps(f64),
+    /// One redstone tick every two server ticks, like vanilla redstone
+    Sync,
+    /// As many ticks as possible, while the world is still updated at the flush rate
+    Unlimited,
+}
+
+impl TickRate {
+    /// The rate in redstone ticks per second, assuming the server runs at 20 tps.
+    pub fn rtps(self) -> f64 {
+        match self {
+            TickRate::Rtps(rtps) => rtps,
+            TickRate::Sync => 10.0,
+            TickRate::Unlimited => f64::INFINITY,
+        }
+    }
+}
+
+impl fmt::Display for TickRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TickRate::Rtps(rtps) => write!(f, "{rtps} rtps"),
+            TickRate::Sync => write!(f, "synced with the server tick"),
+            TickRate::Unlimited => write!(f, "unlimited rtps"),
+        }
+    }
+}
+
+/// Lets the tick loop sleep while there is nothing to simulate.
+///
+/// Anything that gives the tick loop new work, like compiling a circuit, calls [`Scheduler::wake`].
+pub struct Scheduler {
+    running: AtomicBool,
+    wake: Notify,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler {
+            running: AtomicBool::new(true),
+            wake: Notify::new(),
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Makes the tick loop exit the next time it wakes up, and wakes it up.
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+        self.wake.notify_one();
+    }
+
+    pub fn wake(&self) {
+        // If the tick loop is not parked the permit is stored, so a wake up is never lost
+        self.wake.notify_one();
+    }
+
+    /// Waits until [`Scheduler::wake`] or [`Scheduler::stop`] is called.
+    pub async fn park(&self) {
+        self.wake.notified().await;
+    }
+
+    /// Waits until `deadline`, returning early when woken.
+    pub async fn sleep_until(&self, deadline: Instant) {
+        let _ = tokio::time::timeout_at(deadline, self.wake.notified()).await;
+    }
+}
+
+/// Converts the time passed into a whole number of ticks for a given tick rate.
+///
+/// The fraction of a tick that is left over is carried to the next call, so rates that don't divide
+/// the wake up interval still run at the right speed on average.
+pub struct TickClock {
+    last_update: Instant,
+    accumulator: f64,
+    /// World age at the last call to [`TickClock::advance_synced`]
+    last_world_age: Option<i64>,
+}
+
+impl TickClock {
+    pub fn new(now: Instant) -> Self {
+        TickClock {
+            last_update: now,
+            accumulator: 0.0,
+            last_world_age: None,
+        }
+    }
+
+    /// Returns the number of ticks that should have run between the last call and `now`.
+    pub fn advance(&mut self, now: Instant, rtps: f64) -> u64 {
+        self.accumulator += now.duration_since(self.last_update).as_secs_f64() * rtps;
+        self.last_update = now;
+        self.last_world_age = None;
+
+        self.take_ticks()
+    }
+
+    /// Returns the number of ticks for the server ticks that passed since the last call, where a
+    /// redstone tick is two server ticks.
+    pub fn advance_synced(&mut self, now: Instant, world_age: i64) -> u64 {
+        if let Some(last_world_age) = self.last_world_age {
+            self.accumulator += (world_age - last_world_age).max(0) as f64 / 2.0;
+        }
+        self.last_update = now;
+        self.last_world_age = Some(world_age);
+
+        self.take_ticks()
+    }
+
+    /// Starts counting from `now` again, dropping the ticks that were due before.
+    pub fn restart(&mut self, now: Instant) {
+        self.last_update = now;
+        self.accumulator = 0.0;
+        self.last_world_age = None;
+    }
+
+    fn take_ticks(&mut self) -> u64 {
+        let ticks = self.accumulator.floor();
+        self.accumulator -= ticks;
+        ticks as u64
+    }
+
+    /// The moment the next whole tick is due.
+    pub fn next_tick(&self, rtps: f64) -> Instant {
+        let remaining = (1.0 - self.accumulator) / rtps;
+        self.last_update + Duration::from_secs_f64(remaining.max(0.0))
+    }
+}
+
+pub struct LagReport {
+    pub measured_rtps: f64,
+    pub target_rtps: f64,
+}
+
+/// Measures how many ticks a circuit actually ran compared to how many were due.
+pub struct LagMonitor {
+    window_start: Instant,
+    ticks_due: u64,
+    ticks_run: u64,
+    last_warning: Option<Instant>,
+    /// The tick rate achieved during the last full measurement window
+    pub measured_rtps: Option<f64>,
+}
+
+impl LagMonitor {
+    pub fn new(now: Instant) -> Self {
+        LagMonitor {
+            window_start: now,
+            ticks_due: 0,
+            ticks_run: 0,
+            last_warning: None,
+            measured_rtps: None,
+        }
+    }
+
+    /// Records an update of the circuit, returns a report at the end of a measurement window in
+    /// which ticks were dropped.
+    pub fn record(&mut self, now: Instant, ticks_due: u64, ticks_run: u64) -> Option<LagReport> {
+        self.ticks_due += ticks_due;
+        self.ticks_run += ticks_run;
+
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed < MEASURE_WINDOW {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let measured_rtps = self.ticks_run as f64 / seconds;
+        let target_rtps = self.ticks_due as f64 / seconds;
+        let behind = self.ticks_run < self.ticks_due;
+
+        self.measured_rtps = Some(measured_rtps);
+        self.window_start = now;
+        self.ticks_due = 0;
+        self.ticks_run = 0;
+
+        behind.then_some(LagReport {
+            measured_rtps,
+            target_rtps,
+        })
+    }
+
+    /// Whether the owner should be warned again, this limits warnings to one per
+    /// [`WARNING_INTERVAL`].
+    pub fn should_warn(&mut self, now: Instant) -> bool {
+        if self
+            .last_warning
+            .is_some_and(|last_warning| now.duration_since(last_warning) < WARNING_INTERVAL)
+        {
+            return false;
+        }
+        self.last_warning = Some(now);
+        true
+    }
+}
@@ -0,0 +1,606 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+};
+
+use nbt::Value;
+use pumpkin_data::{Block, BlockState, item::Item};
+use pumpkin_util::math::position::BlockPos;
+use pumpkin_world::{
+    block::entities::{barrel::BarrelBlockEntity, comparator::ComparatorBlockEntity},
+    item::ItemStack,
+    world::BlockFlags,
+};
+
+use crate::{
+    compile::{self, ScannedBlock},
+    export,
+};
+
+/// Schematics are read from and written to this folder inside the plugin data folder.
+const SCHEMATIC_FOLDER: &str = "schematics";
+/// Version of the Sponge Schematic format that is written
+const SCHEMATIC_VERSION: i32 = 3;
+/// Minecraft data version of the blocks in written schematics, 1.21.5
+const DATA_VERSION: i32 = 4325;
+const AIR: &str = "minecraft:air";
+
+pub fn schematic_path(data_folder: &Path, name: &str) -> PathBuf {
+    data_folder
+        .join(SCHEMATIC_FOLDER)
+        .join(format!("{}.schem", export::file_name(name)))
+}
+
+pub struct SchematicBlockEntity {
+    /// Position relative to the lowest corner of the schematic
+    pub pos: [i32; 3],
+    pub id: String,
+    pub data: HashMap<String, Value>,
+}
+
+/// The contents of a Sponge Schematic v3 file.
+pub struct Schematic {
+    pub width: i32,
+    pub height: i32,
+    pub length: i32,
+    /// Block states like `minecraft:repeater[delay=1,facing=north,locked=false,powered=false]`
+    pub palette: Vec<String>,
+    /// Index into the palette of every block, in x, z, y order
+    pub blocks: Vec<u32>,
+    pub block_entities: Vec<SchematicBlockEntity>,
+}
+
+/// Checks that the area between `p1` and `p2` (inclusive) fits in a schematic, so it isn't read
+/// out of the world only to fail when saving.
+pub fn check_size(p1: BlockPos, p2: BlockPos) -> Result<(), String> {
+    dimension((p1.0.x - p2.0.x).abs() + 1)?;
+    dimension((p1.0.y - p2.0.y).abs() + 1)?;
+    dimension((p1.0.z - p2.0.z).abs() + 1)?;
+    Ok(())
+}
+
+/// Sizes are stored as unsigned shorts.
+fn dimension(size: i32) -> Result<u16, String> {
+    u16::try_from(size).map_err(|_| {
+        format!(
+            "Schematics can be at most {} blocks along each axis, not {size}",
+            u16::MAX
+        )
+    })
+}
+
+impl Schematic {
+    /// Copies the blocks between `p1` and `p2` (inclusive) out of the Pumpkin world.
+    pub async fn from_world(world: &pumpkin::world::World, p1: BlockPos, p2: BlockPos) -> Self {
+        let width = (p1.0.x - p2.0.x).abs() + 1;
+        let height = (p1.0.y - p2.0.y).abs() + 1;
+        let length = (p1.0.z - p2.0.z).abs() + 1;
+
+        let mut schematic = Schematic {
+            width,
+            height,
+            length,
+            palette: vec![AIR.to_string()],
+            blocks: vec![0; width as usize * height as usize * length as usize],
+            block_entities: Vec::new(),
+        };
+        let mut palette_ids = HashMap::new();
+
+        for scanned in compile::scan_region(world, p1, p2).await {
+            let ScannedBlock {
+                pos,
+                local,
+                block,
+                state,
+            } = scanned;
+
+            let block_state = block_state_string(block, state);
+            let id = *palette_ids
+                .entry(block_state)
+                .or_insert_with_key(|block_state| {
+                    schematic.palette.push(block_state.clone());
+                    schematic.palette.len() as u32 - 1
+                });
+            let index = schematic.index(local.x, local.y, local.z);
+            schematic.blocks[index] = id;
+
+            if let Some((id, data)) = read_block_entity(world, pos).await {
+                schematic.block_entities.push(SchematicBlockEntity {
+                    pos: [local.x, local.y, local.z],
+                    id,
+                    data,
+                });
+            }
+        }
+
+        schematic
+    }
+
+    /// Reads a schematic, failing before reading the blocks when it has more than `max_volume`.
+    pub fn load(path: &Path, max_volume: u64) -> Result<Schematic, String> {
+        let file =
+            File::open(path).map_err(|err| format!("Failed to open {}: {err}", path.display()))?;
+        let root = nbt::Blob::from_gzip_reader(&mut BufReader::new(file))
+            .map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+
+        let invalid =
+            |reason: &str| format!("{} is not a Sponge schematic v3, {reason}", path.display());
+
+        let Some(Value::Compound(schematic)) = root.get("Schematic") else {
+            return Err(invalid("the Schematic tag is missing"));
+        };
+        if int(schematic.get("Version")) != Some(SCHEMATIC_VERSION) {
+            return Err(invalid("the version is not supported"));
+        }
+
+        let (Some(width), Some(height), Some(length)) = (
+            dimension(schematic.get("Width")),
+            dimension(schematic.get("Height")),
+            dimension(schematic.get("Length")),
+        ) else {
+            return Err(invalid("the size is missing"));
+        };
+        // Each size fits in 16 bits, so this can't overflow
+        let volume = width as u64 * height as u64 * length as u64;
+        if volume > max_volume {
+            return Err(format!(
+                "Schematic of {volume} blocks is larger than the limit of {max_volume} blocks"
+            ));
+        }
+
+        let Some(Value::Compound(blocks)) = schematic.get("Blocks") else {
+            return Err(invalid("the Blocks tag is missing"));
+        };
+        let (Some(Value::Compound(palette_ids)), Some(Value::ByteArray(data))) =
+            (blocks.get("Palette"), blocks.get("Data"))
+        else {
+            return Err(invalid("the palette or block data is missing"));
+        };
+
+        let mut palette = vec![AIR.to_string(); palette_ids.len()];
+        for (block_state, id) in palette_ids {
+            match int(Some(id)) {
+                Some(id) if id >= 0 && (id as usize) < palette.len() => {
+                    palette[id as usize] = block_state.clone();
+                }
+                _ => return Err(invalid("the palette has ids out of range")),
+            }
+        }
+
+        let block_ids = read_varints(data).ok_or_else(|| invalid("the block data is truncated"))?;
+        if block_ids.len() as u64 != volume
+            || block_ids.iter().any(|&id| id as usize >= palette.len())
+        {
+            return Err(invalid("the block data does not match the size"));
+        }
+
+        let mut block_entities = Vec::new();
+        if let Some(Value::List(entities)) = blocks.get("BlockEntities") {
+            for entity in entities {
+                let Value::Compound(entity) = entity else {
+                    continue;
+                };
+                let (Some(Value::IntArray(pos)), Some(Value::String(id))) =
+                    (entity.get("Pos"), entity.get("Id"))
+                else {
+                    continue;
+                };
+                let &[x, y, z] = pos.as_slice() else {
+                    continue;
+                };
+                if !in_bounds([x, y, z], [width, height, length]) {
+                    log::warn!("Skipping block entity {id} outside of {}", path.display());
+                    continue;
+                }
+
+                let data = match entity.get("Data") {
+                    Some(Value::Compound(data)) => data.clone().into_iter().collect(),
+                    _ => HashMap::new(),
+                };
+                block_entities.push(SchematicBlockEntity {
+                    pos: [x, y, z],
+                    id: id.clone(),
+                    data,
+                });
+            }
+        }
+
+        Ok(Schematic {
+            width,
+            height,
+            length,
+            palette,
+            blocks: block_ids,
+            block_entities,
+        })
+    }
+
+    /// Writes the schematic into the world with its lowest corner at `origin`, replacing everything
+    /// in the area including air.
+    pub async fn paste(&self, world: &pumpkin::world::World, origin: BlockPos) {
+        let states: Vec<u16> = self
+            .palette
+            .iter()
+            .map(|block_state| {
+                parse_block_state(block_state).unwrap_or_else(|| {
+                    log::warn!("Pasting unknown block {block_state} as air");
+                    Block::AIR.default_state.id
+                })
+            })
+            .collect();
+
+        for y in 0..self.height {
+            for z in 0..self.length {
+                for x in 0..self.width {
+                    let state = states[self.blocks[self.index(x, y, z)] as usize];
+                    let pos = BlockPos::new(origin.0.x + x, origin.0.y + y, origin.0.z + z);
+                    world
+                        .set_block_state(&pos, state, BlockFlags::empty())
+                        .await;
+                }
+            }
+        }
+
+        for entity in &self.block_entities {
+            // Never write outside of the pasted area
+            if !in_bounds(entity.pos, [self.width, self.height, self.length]) {
+                continue;
+            }
+            let [x, y, z] = entity.pos;
+            let pos = BlockPos::new(origin.0.x + x, origin.0.y + y, origin.0.z + z);
+            write_block_entity(world, pos, &entity.id, &entity.data).await;
+        }
+    }
+
+    pub fn index(&self, x: i32, y: i32, z: i32) -> usize {
+        (x + z * self.width + y * self.width * self.length) as usize
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let dimension = |size: i32| dimension(size).map(|size| Value::Short(size as i16));
+        let (width, height, length) = (
+            dimension(self.width)?,
+            dimension(self.height)?,
+            dimension(self.length)?,
+        );
+
+        let palette = self
+            .palette
+            .iter()
+            .enumerate()
+            .map(|(id, block_state)| (block_state.clone(), Value::Int(id as i32)))
+            .collect();
+
+        let mut data = Vec::new();
+        for &id in &self.blocks {
+            write_varint(&mut data, id);
+        }
+
+        let block_entities = self
+            .block_entities
+            .iter()
+            .map(|entity| {
+                Value::Compound(
+                    [
+                        ("Pos".to_string(), Value::IntArray(entity.pos.to_vec())),
+                        ("Id".to_string(), Value::String(entity.id.clone())),
+                        (
+                            "Data".to_string(),
+                            Value::Compound(entity.data.clone().into_iter().collect()),
+                        ),
+                    ]
+                    .into_iter()
+                    .collect(),
+                )
+            })
+            .collect();
+
+        let blocks = [
+            ("Palette".to_string(), Value::Compound(palette)),
+            ("Data".to_string(), Value::ByteArray(data)),
+            ("BlockEntities".to_string(), Value::List(block_entities)),
+        ];
+        let schematic = [
+            ("Version".to_string(), Value::Int(SCHEMATIC_VERSION)),
+            ("DataVersion".to_string(), Value::Int(DATA_VERSION)),
+            ("Width".to_string(), width),
+            ("Height".to_string(), height),
+            ("Length".to_string(), length),
+            ("Offset".to_string(), Value::IntArray(vec![0, 0, 0])),
+            (
+                "Blocks".to_string(),
+                Value::Compound(blocks.into_iter().collect()),
+            ),
+        ];
+
+        let mut root = nbt::Blob::new();
+        root.insert(
+            "Schematic",
+            Value::Compound(schematic.into_iter().collect()),
+        )
+        .map_err(|err| format!("Failed to serialize schematic: {err}"))?;
+
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder)
+                .map_err(|err| format!("Failed to create {}: {err}", folder.display()))?;
+        }
+        let file = File::create(path)
+            .map_err(|err| format!("Failed to create {}: {err}", path.display()))?;
+        root.to_gzip_writer(&mut BufWriter::new(file))
+            .map_err(|err| format!("Failed to write {}: {err}", path.display()))
+    }
+}
+
+/// The block state in the format used by schematics and commands.
+pub fn block_state_string(block: &Block, state: &BlockState) -> String {
+    let mut block_state = format!("minecraft:{}", block.name);
+
+    if let Some(properties) = block.properties(state.id) {
+        let mut properties = properties.to_props();
+        if !properties.is_empty() {
+            properties.sort();
+            let properties: Vec<String> = properties
+                .into_iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect();
+            block_state += &format!("[{}]", properties.join(","));
+        }
+    }
+
+    block_state
+}
+
+/// Parses a block state like `minecraft:lever[face=wall,facing=north,powered=false]` into the
+/// Pumpkin state id, properties that are missing or unknown keep their default.
+pub fn parse_block_state(block_state: &str) -> Option<u16> {
+    let (name, properties) = match block_state.split_once('[') {
+        Some((name, properties)) => (name, properties.strip_suffix(']')?),
+        None => (block_state, ""),
+    };
+    let block = Block::from_registry_key(name.strip_prefix("minecraft:").unwrap_or(name))?;
+
+    let properties: Vec<(String, String)> = properties
+        .split(',')
+        .filter_map(|property| property.split_once('='))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    if properties.is_empty() {
+        return Some(block.default_state.id);
+    }
+
+    Some(
+        block
+            .from_properties(properties)
+            .map_or(block.default_state.id, |properties| {
+                properties.to_state_id(block)
+            }),
+    )
+}
+
+/// Reads the block entities that compiled circuits care about, as the schematic id and data.
+async fn read_block_entity(
+    world: &pumpkin::world::World,
+    pos: BlockPos,
+) -> Option<(String, HashMap<String, Value>)> {
+    let entity = world.get_block_entity(&pos).await?;
+
+    if let Some(comparator) = entity.as_any().downcast_ref::<ComparatorBlockEntity>() {
+        let output_signal = comparator.output_signal.load(Ordering::Relaxed);
+        let data = HashMap::from([("OutputSignal".to_string(), Value::Int(output_signal as i32))]);
+        return Some(("minecraft:comparator".to_string(), data));
+    }
+
+    if let Some(barrel) = entity.as_any().downcast_ref::<BarrelBlockEntity>() {
+        let mut items = Vec::new();
+        for (slot, stack) in barrel.items.iter().enumerate() {
+            let stack = stack.lock().await;
+            if stack.item_count == 0 {
+                continue;
+            }
+
+            let item = [
+                ("Slot".to_string(), Value::Byte(slot as i8)),
+                (
+                    "id".to_string(),
+                    Value::String(format!("minecraft:{}", stack.item.registry_key)),
+                ),
+                ("count".to_string(), Value::Int(stack.item_count as i32)),
+            ];
+            items.push(Value::Compound(item.into_iter().collect()));
+        }
+
+        let data = HashMap::from([("Items".to_string(), Value::List(items))]);
+        return Some(("minecraft:barrel".to_string(), data));
+    }
+
+    None
+}
+
+/// Places the block entities written by [`read_block_entity`], replacing the ones already there.
+async fn write_block_entity(
+    world: &pumpkin::world::World,
+    pos: BlockPos,
+    id: &str,
+    data: &HashMap<String, Value>,
+) {
+    match id {
+        "minecraft:comparator" => {
+            let comparator = ComparatorBlockEntity::new(pos);
+            let output_signal = int(data.get("OutputSignal")).unwrap_or(0).clamp(0, 15);
+            comparator
+                .output_signal
+                .store(output_signal as u8, Ordering::Relaxed);
+            world.add_block_entity(Arc::new(comparator)).await;
+        }
+        "minecraft:barrel" => {
+            let barrel = BarrelBlockEntity::new(pos);
+            for (slot, item, count) in container_items(data) {
+                let Some(stack) = slot.and_then(|slot| barrel.items.get(slot as usize)) else {
+                    continue;
+                };
+                *stack.lock().await = ItemStack::new(count.clamp(0, 255) as u8, item);
+            }
+            world.add_block_entity(Arc::new(barrel)).await;
+        }
+        _ => log::warn!("Skipping unsupported block entity {id} at {pos:?}"),
+    }
+}
+
+/// The slot, item and count of the `Items` of a container block entity, skipping items that are
+/// unknown or have no count.
+pub fn container_items(data: &HashMap<String, Value>) -> Vec<(Option<i32>, &'static Item, i32)> {
+    let Some(Value::List(items)) = data.get("Items") else {
+        return Vec::new();
+    };
+
+    items
+        .iter()
+        .filter_map(|item| {
+            let Value::Compound(item) = item else {
+                return None;
+            };
+            let (Some(Value::String(id)), Some(count)) = (item.get("id"), int(item.get("count")))
+            else {
+                return None;
+            };
+            let item_type = Item::from_registry_key(id.strip_prefix("minecraft:").unwrap_or(id))?;
+            Some((int(item.get("Slot")), item_type, count))
+        })
+        .collect()
+}
+
+/// Reads any integer tag, as other tools don't always use the same size.
+pub fn int(value: Option<&Value>) -> Option<i32> {
+    match value? {
+        Value::Byte(value) => Some(*value as i32),
+        Value::Short(value) => Some(*value as i32),
+        Value::Int(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Reads a width, height or length, which are stored as unsigned shorts.
+fn dimension(value: Option<&Value>) -> Option<i32> {
+    match value? {
+        Value::Short(value) => Some(*value as u16 as i32),
+        _ => None,
+    }
+}
+
+/// Whether a position relative to the lowest corner is inside a schematic of `size`.
+fn in_bounds(pos: [i32; 3], size: [i32; 3]) -> bool {
+    pos.iter()
+        .zip(size)
+        .all(|(&coord, size)| (0..size).contains(&coord))
+}
+
+fn read_varints(data: &[i8]) -> Option<Vec<u32>> {
+    let mut values = Vec::new();
+    let mut value = 0;
+    let mut shift = 0;
+    for &byte in data {
+        let byte = byte as u8;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+            if shift > 28 {
+                return None;
+            }
+        }
+    }
+    // The last varint must not be cut off
+    (shift == 0).then_some(values)
+}
+
+fn write_varint(data: &mut Vec<i8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte as i8);
+            return;
+        }
+        data.push((byte | 0x80) as i8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_varints() {
+        let values = [0, 1, 127, 128, 300, 16_384, u32::MAX];
+        let mut data = Vec::new();
+        for value in values {
+            write_varint(&mut data, value);
+        }
+
+        assert_eq!(read_varints(&data), Some(values.to_vec()));
+    }
+
+    #[test]
+    fn rejects_truncated_varints() {
+        let mut data = Vec::new();
+        write_varint(&mut data, 300);
+        data.pop();
+
+        assert_eq!(read_varints(&data), None);
+    }
+
+    #[test]
+    fn rejects_saving_oversized_schematics() {
+        let schematic = Schematic {
+            width: u16::MAX as i32 + 1,
+            height: 1,
+            length: 1,
+            palette: vec![AIR.to_string()],
+            blocks: vec![0; u16::MAX as usize + 1],
+            block_entities: Vec::new(),
+        };
+
+        let path = std::env::temp_dir().join("redpiler-oversized.schem");
+        assert!(schematic.save(&path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn checks_the_size_of_areas() {
+        let origin = BlockPos::new(0, 0, 0);
+        let max = u16::MAX as i32;
+
+        assert!(check_size(origin, BlockPos::new(max - 1, 0, -max + 1)).is_ok());
+        assert!(check_size(origin, BlockPos::new(max, 0, 0)).is_err());
+        assert!(check_size(BlockPos::new(0, -max, 0), origin).is_err());
+    }
+
+    #[test]
+    fn checks_block_entity_bounds() {
+        assert!(in_bounds([0, 0, 0], [1, 1, 1]));
+        assert!(in_bounds([4, 0, 2], [5, 1, 3]));
+        assert!(!in_bounds([5, 0, 0], [5, 1, 3]));
+        assert!(!in_bounds([0, -1, 0], [5, 1, 3]));
+    }
+
+    #[test]
+    fn parses_block_states() {
+        let lever = parse_block_state("minecraft:lever[face=wall,facing=east,powered=true]");
+        assert_eq!(
+            lever.map(|id| block_state_string(Block::from_state_id(id), BlockState::from_id(id))),
+            Some("minecraft:lever[face=wall,facing=east,powered=true]".to_string())
+        );
+
+        assert_eq!(
+            parse_block_state("redstone_lamp"),
+            Some(Block::REDSTONE_LAMP.default_state.id)
+        );
+        assert_eq!(parse_block_state("minecraft:not_a_block"), None);
+    }
+}
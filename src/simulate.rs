@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use mchprs_blocks::{BlockPos, block_entities::BlockEntity, blocks::Block};
+use mchprs_redpiler::{Compiler, CompilerOptions};
+use mchprs_world::World;
+use nbt::Value;
+use pumpkin_data::BlockState;
+
+use crate::{
+    compile,
+    fixed_world::TestWorld,
+    pumpkin_plot,
+    schematic::{self, Schematic},
+};
+
+/// The most ticks `/rp simulate` runs.
+pub const MAX_TICKS: i32 = 1_000_000;
+
+/// Slots of a barrel, used for the comparator output of barrels in schematics.
+const BARREL_SLOTS: usize = 27;
+
+/// Right clicks a block at the start of a tick, like a player pressing a button or flipping a
+/// lever.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptedInput {
+    pub tick: u64,
+    /// Position relative to the lowest corner of the schematic
+    pub pos: BlockPos,
+}
+
+/// A lamp or trapdoor after the simulation, as those are what a circuit shows its output on.
+pub struct OutputState {
+    pub pos: BlockPos,
+    pub name: &'static str,
+    pub powered: bool,
+}
+
+/// Parses inputs like `0:1,0,2 10:1,0,2`, which uses the block at 1, 0, 2 at tick 0 and 10.
+pub fn parse_inputs(script: &str) -> Result<Vec<ScriptedInput>, String> {
+    let mut inputs = Vec::new();
+    for input in script.split_whitespace() {
+        let invalid = || format!("Invalid input {input}, expected <tick>:<x>,<y>,<z>");
+
+        let (tick, pos) = input.split_once(':').ok_or_else(invalid)?;
+        let tick = tick.parse().map_err(|_| invalid())?;
+        let pos: Vec<i32> = pos
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let &[x, y, z] = pos.as_slice() else {
+            return Err(invalid());
+        };
+
+        inputs.push(ScriptedInput {
+            tick,
+            pos: BlockPos::new(x, y, z),
+        });
+    }
+    Ok(inputs)
+}
+
+/// Builds a [`TestWorld`] with the blocks of the schematic, with its lowest corner at 0, 0, 0.
+///
+/// Blocks that don't exist in this version are left as air.
+pub fn build_world(schematic: &Schematic) -> TestWorld {
+    let palette: Vec<Block> = schematic
+        .palette
+        .iter()
+        .map(
+            |block_state| match schematic::parse_block_state(block_state) {
+                Some(id) => compile::to_mchprs_block(
+                    pumpkin_data::Block::from_state_id(id),
+                    BlockState::from_id(id),
+                ),
+                None => {
+                    log::warn!("Simulating unknown block {block_state} as air");
+                    Block::Air {}
+                }
+            },
+        )
+        .collect();
+
+    let mut plot = TestWorld::new(schematic.width, schematic.height, schematic.length);
+    for y in 0..schematic.height {
+        for z in 0..schematic.length {
+            for x in 0..schematic.width {
+                let block = palette[schematic.blocks[schematic.index(x, y, z)] as usize];
+                if block != (Block::Air {}) {
+                    plot.set_block(BlockPos::new(x, y, z), block);
+                }
+            }
+        }
+    }
+
+    for entity in &schematic.block_entities {
+        let [x, y, z] = entity.pos;
+        if let Some(block_entity) = to_mchprs_block_entity(&entity.id, &entity.data) {
+            plot.set_block_entity(BlockPos::new(x, y, z), block_entity);
+        }
+    }
+
+    plot
+}
+
+/// Compiles the schematic without a Pumpkin world, runs `ticks` ticks with `inputs` and returns
+/// the state of every lamp and trapdoor afterwards.
+pub fn simulate(
+    schematic: &Schematic,
+    options: CompilerOptions,
+    ticks: u64,
+    inputs: &[ScriptedInput],
+) -> Result<Vec<OutputState>, String> {
+    let mut plot = build_world(schematic);
+    check_inputs(&plot, inputs)?;
+    let mut compiler = compile::compile_world(&mut plot, options);
+    run(&mut compiler, ticks, inputs);
+    compiler.flush(&mut plot);
+
+    let mut outputs = Vec::new();
+    for y in 0..schematic.height {
+        for z in 0..schematic.length {
+            for x in 0..schematic.width {
+                let pos = BlockPos::new(x, y, z);
+                let (name, powered) = match plot.get_block(pos) {
+                    Block::RedstoneLamp { lit } => ("lamp", lit),
+                    Block::IronTrapdoor { powered, .. } => ("trapdoor", powered),
+                    _ => continue,
+                };
+                outputs.push(OutputState { pos, name, powered });
+            }
+        }
+    }
+    Ok(outputs)
+}
+
+/// Checks that every input is at a lever, button or pressure plate, as the compiler can't use
+/// other blocks.
+pub fn check_inputs(plot: &TestWorld, inputs: &[ScriptedInput]) -> Result<(), String> {
+    for input in inputs {
+        if !pumpkin_plot::is_input_block(plot.get_block(input.pos)) {
+            return Err(format!(
+                "There is no lever, button or pressure plate at {}, {}, {} to use",
+                input.pos.x, input.pos.y, input.pos.z
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs `ticks` ticks, using the blocks of `inputs` right before their tick.
+pub fn run(compiler: &mut Compiler, ticks: u64, inputs: &[ScriptedInput]) {
+    let mut inputs = inputs.to_vec();
+    inputs.sort_by_key(|input| input.tick);
+
+    let mut tick = 0;
+    for input in inputs.iter().filter(|input| input.tick < ticks) {
+        compiler.tickn(input.tick - tick);
+        tick = input.tick;
+        compiler.on_use_block(input.pos);
+    }
+    compiler.tickn(ticks - tick);
+}
+
+/// Converts the block entities written by [`Schematic::from_world`].
+fn to_mchprs_block_entity(id: &str, data: &HashMap<String, Value>) -> Option<BlockEntity> {
+    match id {
+        "minecraft:comparator" => {
+            let output_strength = schematic::int(data.get("OutputSignal")).unwrap_or(0);
+            Some(BlockEntity::Comparator {
+                output_strength: output_strength.clamp(0, 15) as u8,
+            })
+        }
+        "minecraft:barrel" => {
+            let fullness_sum: f32 = schematic::container_items(data)
+                .into_iter()
+                .map(|(_, item, count)| count as f32 / compile::max_stack_size(item) as f32)
+                .sum();
+
+            Some(BlockEntity::Container {
+                comparator_override: compile::comparator_override(fullness_sum, BARREL_SLOTS),
+                inventory: Vec::new(),
+                ty: mchprs_blocks::block_entities::ContainerType::Barrel,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_inputs() {
+        let inputs = parse_inputs("0:1,0,2  10:-1,5,3").unwrap();
+
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(inputs[0].tick, 0);
+        assert_eq!(inputs[0].pos, BlockPos::new(1, 0, 2));
+        assert_eq!(inputs[1].tick, 10);
+        assert_eq!(inputs[1].pos, BlockPos::new(-1, 5, 3));
+        assert!(parse_inputs("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_block_entities_with_any_integer_size() {
+        let item = [
+            ("Slot".to_string(), Value::Byte(0)),
+            (
+                "id".to_string(),
+                Value::String("minecraft:redstone".to_string()),
+            ),
+            ("count".to_string(), Value::Byte(64)),
+        ];
+        let barrel = HashMap::from([(
+            "Items".to_string(),
+            Value::List(vec![Value::Compound(item.into_iter().collect())]),
+        )]);
+        let comparator = HashMap::from([("OutputSignal".to_string(), Value::Short(7))]);
+
+        assert!(matches!(
+            to_mchprs_block_entity("minecraft:barrel", &barrel),
+            Some(BlockEntity::Container {
+                comparator_override: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            to_mchprs_block_entity("minecraft:comparator", &comparator),
+            Some(BlockEntity::Comparator { output_strength: 7 })
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        for script in ["1,0,2", "a:1,0,2", "0:1,0", "0:1,0,2,3", "-1:1,0,2"] {
+            assert!(parse_inputs(script).is_err(), "{script} was accepted");
+        }
+    }
+}
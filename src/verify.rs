@@ -0,0 +1,236 @@
+use std::sync::Arc;
+
+use mchprs_redpiler::CompilerOptions;
+use mchprs_world::World;
+use pumpkin_data::{Block, BlockDirection, BlockState};
+use pumpkin_util::math::position::BlockPos;
+use pumpkin_world::{tick::TickPriority, world::BlockFlags};
+
+use crate::{
+    compile,
+    fixed_world::TestWorld,
+    pumpkin_plot::{self, BlockCache},
+    scheduler::SYNC_POLL_INTERVAL,
+    schematic::{self, Schematic},
+    simulate::{self, ScriptedInput},
+};
+
+/// Ticks `/rp verify` runs without a tick count.
+pub const DEFAULT_TICKS: u64 = 100;
+/// Vanilla redstone runs at 10 ticks per second, so this takes at most two minutes.
+pub const MAX_TICKS: i32 = 1200;
+
+/// Blocks of air between the selection and the copy that vanilla redstone runs in.
+const SCRATCH_GAP: i32 = 2;
+/// Game ticks per redstone tick.
+const GAME_TICKS_PER_TICK: i64 = 2;
+/// Game ticks until a stone button pops back out.
+const STONE_BUTTON_TICKS: u16 = 20;
+
+/// The first blocks that differ between vanilla redstone and the compiled circuit.
+pub struct Mismatch {
+    pub tick: u64,
+    /// Position relative to the lowest corner of the selection
+    pub pos: mchprs_blocks::BlockPos,
+    pub vanilla: String,
+    pub compiled: String,
+    /// Number of other blocks that differ at the same tick
+    pub others: usize,
+    /// Game ticks the world ran past the tick before the blocks were read, as the world is only
+    /// polled. Vanilla may have changed more blocks then, so the mismatch can come from lag.
+    pub skew: i64,
+}
+
+/// The area next to the selection that the copy is placed in, along the x axis.
+pub fn scratch_area(p1: BlockPos, p2: BlockPos) -> (BlockPos, BlockPos) {
+    let offset = (p1.0.x - p2.0.x).abs() + 1 + SCRATCH_GAP;
+    (
+        BlockPos::new(
+            p1.0.x.min(p2.0.x) + offset,
+            p1.0.y.min(p2.0.y),
+            p1.0.z.min(p2.0.z),
+        ),
+        BlockPos::new(
+            p1.0.x.max(p2.0.x) + offset,
+            p1.0.y.max(p2.0.y),
+            p1.0.z.max(p2.0.z),
+        ),
+    )
+}
+
+/// Copies the selection into the scratch area and runs it for `ticks` redstone ticks both with
+/// vanilla redstone and compiled, one compiled tick every two game ticks.
+///
+/// The scratch area must be empty and is cleared again afterwards.
+pub async fn verify(
+    world: &Arc<pumpkin::world::World>,
+    p1: BlockPos,
+    p2: BlockPos,
+    options: CompilerOptions,
+    ticks: u64,
+    inputs: &[ScriptedInput],
+) -> Result<Option<Mismatch>, String> {
+    let (s1, s2) = scratch_area(p1, p2);
+    if !compile::scan_region(world, s1, s2).await.is_empty() {
+        return Err(format!(
+            "The scratch area from {}, {}, {} to {}, {}, {} is not empty",
+            s1.0.x, s1.0.y, s1.0.z, s2.0.x, s2.0.y, s2.0.z
+        ));
+    }
+
+    let schematic = Schematic::from_world(world, p1, p2).await;
+    schematic.paste(world, s1).await;
+
+    let result = run(world, s1, s2, options, ticks, inputs).await;
+
+    // Setting air without updates leaves block entities behind, so those are removed first
+    let air = Block::AIR.default_state.id;
+    for scanned in compile::scan_region(world, s1, s2).await {
+        world.remove_block_entity(&scanned.pos).await;
+        world
+            .set_block_state(&scanned.pos, air, BlockFlags::empty())
+            .await;
+    }
+
+    result
+}
+
+async fn run(
+    world: &Arc<pumpkin::world::World>,
+    s1: BlockPos,
+    s2: BlockPos,
+    options: CompilerOptions,
+    ticks: u64,
+    inputs: &[ScriptedInput],
+) -> Result<Option<Mismatch>, String> {
+    let (mut plot, mut compiler, blocks) = compile::compile_region(world, s1, s2, options).await;
+    simulate::check_inputs(&plot, inputs)?;
+    let to_world = |pos: mchprs_blocks::BlockPos| {
+        BlockPos::new(s1.0.x + pos.x, s1.0.y + pos.y, s1.0.z + pos.z)
+    };
+
+    let start = world.level_time.lock().await.world_age;
+    for tick in 0..ticks {
+        for input in inputs.iter().filter(|input| input.tick == tick) {
+            compiler.on_use_block(input.pos);
+            compiler.flush(&mut plot);
+
+            // Vanilla gets the same input by copying the state the compiler gave the block
+            let block = plot.get_block(input.pos);
+            let pumpkin_block = blocks[&input.pos];
+            if let Some(state) = pumpkin_plot::to_pumpkin_state(block, pumpkin_block) {
+                use_block(world, pumpkin_block, to_world(input.pos), state).await;
+            }
+        }
+
+        compiler.tickn(1);
+        compiler.flush(&mut plot);
+
+        let target = start + (tick as i64 + 1) * GAME_TICKS_PER_TICK;
+        while world.level_time.lock().await.world_age < target {
+            tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+        }
+
+        let mut mismatches = Vec::new();
+        for (pos, compiled) in compiled_states(&plot, &blocks) {
+            let (_, vanilla) = world.get_block_and_state(&to_world(pos)).await;
+            if vanilla.id != compiled {
+                mismatches.push((pos, vanilla.id, compiled));
+            }
+        }
+        let skew = world.level_time.lock().await.world_age - target;
+
+        // Report the lowest position, so the same difference is found every time
+        mismatches.sort_by_key(|(pos, _, _)| (pos.y, pos.z, pos.x));
+        if let Some(&(pos, vanilla, compiled)) = mismatches.first() {
+            return Ok(Some(Mismatch {
+                tick,
+                pos,
+                vanilla: state_string(vanilla),
+                compiled: state_string(compiled),
+                others: mismatches.len() - 1,
+                skew,
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The state of every block the compiler can change, as vanilla should show it. Blocks that can't
+/// be mapped back to a state, like observers, are left out.
+fn compiled_states(plot: &TestWorld, blocks: &BlockCache) -> Vec<(mchprs_blocks::BlockPos, u16)> {
+    blocks
+        .iter()
+        .filter_map(|(&pos, &pumpkin_block)| {
+            let state = pumpkin_plot::to_pumpkin_state(plot.get_block(pos), pumpkin_block)?;
+            Some((pos, state))
+        })
+        .collect()
+}
+
+/// Sets the state of a lever or button and updates the blocks around it like using it does.
+async fn use_block(
+    world: &Arc<pumpkin::world::World>,
+    block: &'static Block,
+    pos: BlockPos,
+    state: u16,
+) {
+    world
+        .set_block_state(&pos, state, BlockFlags::NOTIFY_ALL)
+        .await;
+    if block.id == Block::STONE_BUTTON.id {
+        world
+            .schedule_block_tick(block, pos, STONE_BUTTON_TICKS, TickPriority::Normal)
+            .await;
+    }
+
+    // Levers and buttons also power the block they are on
+    world.update_neighbors(&pos, None).await;
+    for direction in BlockDirection::all() {
+        world
+            .update_neighbors(&pos.offset(direction.to_offset()), None)
+            .await;
+    }
+}
+
+fn state_string(state: u16) -> String {
+    schematic::block_state_string(Block::from_state_id(state), BlockState::from_id(state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_area_is_next_to_the_selection() {
+        let (s1, s2) = scratch_area(BlockPos::new(4, 70, -2), BlockPos::new(0, 64, 3));
+
+        assert_eq!(s1, BlockPos::new(7, 64, -2));
+        assert_eq!(s2, BlockPos::new(11, 70, 3));
+    }
+
+    #[test]
+    fn skips_blocks_without_a_compiled_state() {
+        let observer = mchprs_blocks::BlockPos::new(0, 0, 0);
+        let lamp = mchprs_blocks::BlockPos::new(1, 0, 0);
+        let mut plot = TestWorld::new(2, 1, 1);
+        plot.set_block(
+            observer,
+            mchprs_blocks::blocks::Block::Observer {
+                facing: mchprs_blocks::BlockFacing::Up,
+            },
+        );
+        plot.set_block(
+            lamp,
+            mchprs_blocks::blocks::Block::RedstoneLamp { lit: true },
+        );
+        let blocks =
+            BlockCache::from([(observer, &Block::OBSERVER), (lamp, &Block::REDSTONE_LAMP)]);
+
+        let states = compiled_states(&plot, &blocks);
+
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].0, lamp);
+    }
+}
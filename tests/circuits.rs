@@ -0,0 +1,255 @@
+//! Builds small circuits in a `TestWorld`, compiles them and checks their outputs.
+
+use mchprs_blocks::{
+    BlockDirection, BlockPos,
+    block_entities::BlockEntity,
+    blocks::{Block, ComparatorMode, Lever, LeverFace, RedstoneComparator, RedstoneRepeater},
+};
+use mchprs_redpiler::{Compiler, CompilerOptions};
+use mchprs_world::{TickPriority, World};
+use pumpkin_redpiler_plugin::{
+    compile,
+    fixed_world::TestWorld,
+    simulate::{self, ScriptedInput},
+};
+
+/// Enough ticks for any of the circuits below to settle.
+const SETTLE_TICKS: u64 = 20;
+
+const SOLID: Block = Block::IronBlock {};
+
+fn repeater(facing: BlockDirection, powered: bool) -> Block {
+    Block::RedstoneRepeater {
+        repeater: RedstoneRepeater {
+            delay: 1,
+            facing,
+            locked: false,
+            powered,
+        },
+    }
+}
+
+fn subtractor(facing: BlockDirection) -> Block {
+    Block::RedstoneComparator {
+        comparator: RedstoneComparator::new(facing, ComparatorMode::Subtract, false),
+    }
+}
+
+fn floor_lever() -> Block {
+    Block::Lever {
+        lever: Lever::new(LeverFace::Floor, BlockDirection::North, false),
+    }
+}
+
+/// A lever on the side of a block, `facing` away from that block.
+fn wall_lever(facing: BlockDirection) -> Block {
+    Block::Lever {
+        lever: Lever::new(LeverFace::Wall, facing, false),
+    }
+}
+
+fn set(world: &mut TestWorld, blocks: &[((i32, i32, i32), Block)]) {
+    for &((x, y, z), block) in blocks {
+        world.set_block(BlockPos::new(x, y, z), block);
+    }
+}
+
+fn compile(world: &mut TestWorld) -> Compiler {
+    compile::compile_world(world, CompilerOptions::default())
+}
+
+/// Flips the lever at `pos` and lets the circuit settle.
+fn flip(compiler: &mut Compiler, pos: (i32, i32, i32)) {
+    let input = ScriptedInput {
+        tick: 0,
+        pos: BlockPos::new(pos.0, pos.1, pos.2),
+    };
+    simulate::run(compiler, SETTLE_TICKS, &[input]);
+}
+
+fn is_lit(compiler: &mut Compiler, world: &mut TestWorld, pos: (i32, i32, i32)) -> bool {
+    compiler.flush(world);
+    match world.get_block(BlockPos::new(pos.0, pos.1, pos.2)) {
+        Block::RedstoneLamp { lit } => lit,
+        block => panic!("expected a lamp at {pos:?}, found {block:?}"),
+    }
+}
+
+#[test]
+fn lever_lights_lamp() {
+    let mut world = TestWorld::new(2, 1, 1);
+    set(
+        &mut world,
+        &[
+            ((0, 0, 0), floor_lever()),
+            ((1, 0, 0), Block::RedstoneLamp { lit: false }),
+        ],
+    );
+    let mut compiler = compile(&mut world);
+
+    flip(&mut compiler, (0, 0, 0));
+    assert!(is_lit(&mut compiler, &mut world, (1, 0, 0)));
+
+    flip(&mut compiler, (0, 0, 0));
+    assert!(!is_lit(&mut compiler, &mut world, (1, 0, 0)));
+}
+
+/// A torch on a block with a loop of repeaters from the torch back into the block.
+#[test]
+fn torch_clock_oscillates() {
+    let mut world = TestWorld::new(4, 1, 4);
+    set(
+        &mut world,
+        &[
+            ((0, 0, 1), SOLID),
+            (
+                (1, 0, 1),
+                Block::RedstoneWallTorch {
+                    lit: true,
+                    facing: BlockDirection::East,
+                },
+            ),
+            ((1, 0, 0), Block::RedstoneLamp { lit: true }),
+            ((2, 0, 1), repeater(BlockDirection::West, false)),
+            ((3, 0, 1), SOLID),
+            ((3, 0, 2), repeater(BlockDirection::North, false)),
+            ((3, 0, 3), SOLID),
+            ((2, 0, 3), repeater(BlockDirection::East, false)),
+            ((1, 0, 3), repeater(BlockDirection::East, false)),
+            ((0, 0, 3), SOLID),
+            ((0, 0, 2), repeater(BlockDirection::South, false)),
+        ],
+    );
+    // The repeater next to the lit torch is behind, which starts the clock
+    world.schedule_tick(BlockPos::new(2, 0, 1), 1, TickPriority::High);
+    let mut compiler = compile(&mut world);
+
+    let mut lit = is_lit(&mut compiler, &mut world, (1, 0, 0));
+    let mut toggles = 0;
+    for _ in 0..200 {
+        simulate::run(&mut compiler, 1, &[]);
+        let now = is_lit(&mut compiler, &mut world, (1, 0, 0));
+        if now != lit {
+            toggles += 1;
+            lit = now;
+        }
+    }
+
+    assert!(toggles >= 4, "the lamp only toggled {toggles} times");
+}
+
+/// A repeater that is locked by a second repeater pointing into its side.
+#[test]
+fn locked_repeater_latches() {
+    let data = (0, 0, 0);
+    let lock = (1, 0, 2);
+    let lamp = (2, 0, 0);
+
+    let mut world = TestWorld::new(3, 1, 3);
+    set(
+        &mut world,
+        &[
+            (data, floor_lever()),
+            ((1, 0, 0), repeater(BlockDirection::West, false)),
+            (lamp, Block::RedstoneLamp { lit: false }),
+            ((1, 0, 1), repeater(BlockDirection::South, false)),
+            (lock, floor_lever()),
+        ],
+    );
+    let mut compiler = compile(&mut world);
+
+    flip(&mut compiler, data);
+    assert!(is_lit(&mut compiler, &mut world, lamp));
+
+    flip(&mut compiler, lock);
+    flip(&mut compiler, data);
+    assert!(
+        is_lit(&mut compiler, &mut world, lamp),
+        "the locked repeater followed its input"
+    );
+
+    flip(&mut compiler, lock);
+    assert!(!is_lit(&mut compiler, &mut world, lamp));
+}
+
+/// Sum is `A XOR B` from two subtracting comparators, carry is `A AND B` from torches.
+#[test]
+fn half_adder_adds() {
+    let lever_a = (1, 0, 0);
+    let lever_b = (4, 0, 1);
+    let sum = (2, 0, 3);
+    let carry = (5, 1, 1);
+
+    let mut world = TestWorld::new(6, 2, 7);
+    set(
+        &mut world,
+        &[
+            // The levers power A and B into the blocks they are on
+            ((2, 0, 0), SOLID),
+            (lever_a, wall_lever(BlockDirection::West)),
+            ((4, 0, 2), SOLID),
+            (lever_b, wall_lever(BlockDirection::North)),
+            // A - B and B - A into the same block
+            ((2, 0, 1), subtractor(BlockDirection::North)),
+            ((3, 0, 2), subtractor(BlockDirection::East)),
+            ((2, 0, 2), SOLID),
+            (sum, Block::RedstoneLamp { lit: false }),
+            // B into the side of A - B
+            ((3, 0, 1), repeater(BlockDirection::East, false)),
+            // A around the sum lamp into the side of B - A
+            ((1, 0, 1), repeater(BlockDirection::North, false)),
+            ((1, 0, 2), SOLID),
+            ((1, 0, 3), repeater(BlockDirection::North, false)),
+            ((1, 0, 4), repeater(BlockDirection::North, false)),
+            ((1, 0, 5), repeater(BlockDirection::North, false)),
+            ((1, 0, 6), SOLID),
+            ((2, 0, 6), repeater(BlockDirection::West, false)),
+            ((3, 0, 6), SOLID),
+            ((3, 0, 5), repeater(BlockDirection::South, false)),
+            ((3, 0, 4), repeater(BlockDirection::South, false)),
+            ((3, 0, 3), repeater(BlockDirection::South, false)),
+            // NOT A and NOT B into the same block, with a torch on it for the carry
+            ((2, 1, 0), Block::RedstoneTorch { lit: true }),
+            ((3, 1, 0), repeater(BlockDirection::West, true)),
+            ((4, 1, 2), Block::RedstoneTorch { lit: true }),
+            ((4, 1, 1), repeater(BlockDirection::South, true)),
+            ((4, 1, 0), SOLID),
+            (
+                (5, 1, 0),
+                Block::RedstoneWallTorch {
+                    lit: false,
+                    facing: BlockDirection::East,
+                },
+            ),
+            (carry, Block::RedstoneLamp { lit: false }),
+        ],
+    );
+    for pos in [BlockPos::new(2, 0, 1), BlockPos::new(3, 0, 2)] {
+        world.set_block_entity(pos, BlockEntity::Comparator { output_strength: 0 });
+    }
+    let mut compiler = compile(&mut world);
+    simulate::run(&mut compiler, SETTLE_TICKS, &[]);
+
+    let mut check = |compiler: &mut Compiler, a: bool, b: bool| {
+        assert_eq!(
+            is_lit(compiler, &mut world, sum),
+            a != b,
+            "sum of {a} and {b}"
+        );
+        assert_eq!(
+            is_lit(compiler, &mut world, carry),
+            a && b,
+            "carry of {a} and {b}"
+        );
+    };
+
+    check(&mut compiler, false, false);
+    flip(&mut compiler, lever_a);
+    check(&mut compiler, true, false);
+    flip(&mut compiler, lever_b);
+    check(&mut compiler, true, true);
+    flip(&mut compiler, lever_a);
+    check(&mut compiler, false, true);
+    flip(&mut compiler, lever_b);
+    check(&mut compiler, false, false);
+}
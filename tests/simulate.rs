@@ -0,0 +1,86 @@
+//! Simulates schematics without a Pumpkin world.
+
+use mchprs_blocks::BlockPos;
+use mchprs_redpiler::CompilerOptions;
+use pumpkin_redpiler_plugin::{
+    schematic::Schematic,
+    simulate::{self, ScriptedInput},
+};
+
+/// A lever with a lamp on both sides, in a 3 by 1 by 1 schematic.
+fn lever_between_lamps() -> Schematic {
+    Schematic {
+        width: 3,
+        height: 1,
+        length: 1,
+        palette: vec![
+            "minecraft:air".to_string(),
+            "minecraft:redstone_lamp[lit=false]".to_string(),
+            "minecraft:lever[face=floor,facing=north,powered=false]".to_string(),
+        ],
+        blocks: vec![1, 2, 1],
+        block_entities: Vec::new(),
+    }
+}
+
+fn use_lever(tick: u64) -> ScriptedInput {
+    ScriptedInput {
+        tick,
+        pos: BlockPos::new(1, 0, 0),
+    }
+}
+
+#[test]
+fn reports_lamps() {
+    let outputs =
+        simulate::simulate(&lever_between_lamps(), CompilerOptions::default(), 10, &[]).unwrap();
+
+    let positions: Vec<_> = outputs.iter().map(|output| output.pos).collect();
+    assert_eq!(positions, [BlockPos::new(0, 0, 0), BlockPos::new(2, 0, 0)]);
+    assert!(
+        outputs
+            .iter()
+            .all(|output| output.name == "lamp" && !output.powered)
+    );
+}
+
+#[test]
+fn applies_scripted_inputs() {
+    let schematic = lever_between_lamps();
+
+    let outputs =
+        simulate::simulate(&schematic, CompilerOptions::default(), 10, &[use_lever(2)]).unwrap();
+    assert!(outputs.iter().all(|output| output.powered));
+
+    // Flipping the lever back turns the lamps off again
+    let inputs = [use_lever(2), use_lever(9)];
+    let outputs = simulate::simulate(&schematic, CompilerOptions::default(), 30, &inputs).unwrap();
+    assert!(outputs.iter().all(|output| !output.powered));
+
+    // Inputs after the last tick are never used
+    let outputs =
+        simulate::simulate(&schematic, CompilerOptions::default(), 10, &[use_lever(10)]).unwrap();
+    assert!(outputs.iter().all(|output| !output.powered));
+}
+
+#[test]
+fn rejects_inputs_that_are_not_levers_buttons_or_pressure_plates() {
+    let lamp = ScriptedInput {
+        tick: 0,
+        pos: BlockPos::new(0, 0, 0),
+    };
+    let outside = ScriptedInput {
+        tick: 0,
+        pos: BlockPos::new(5, 0, 0),
+    };
+
+    for input in [lamp, outside] {
+        let result = simulate::simulate(
+            &lever_between_lamps(),
+            CompilerOptions::default(),
+            10,
+            &[input],
+        );
+        assert!(result.is_err());
+    }
+}